@@ -0,0 +1,32 @@
+/*
+Throughput of the AesKey backends
+cargo run --release --example bench [<MiB>]
+*/
+use std::env;
+use std::time::Instant;
+use aes::{AesKey, Backend};
+use aes::ecb::ecb_encrypt;
+//
+fn main(){
+  let mib:usize = env::args().nth(1).and_then(|n| n.parse().ok()).unwrap_or(4);
+  let data:Vec<u8> = (0..mib*1024*1024).map(|i| i as u8).collect();
+  let key:[u8;32] = [0x42; 32];
+  let reference = ecb_encrypt(&AesKey::new(&key).unwrap(),&data);
+  println!("{:<14}{:>10}{:>10}","backend","MiB/s","vs Table");
+  let mut table_speed = 0.0;
+  for backend in [Backend::Table, Backend::TTable, Backend::ConstantTime, Backend::Bitsliced, Backend::Vpaes, Backend::AesNi] {
+    let aes = AesKey::with_backend(&key,backend).unwrap();
+    if aes.backend != backend {
+      println!("{:<14}{:>10}",format!("{backend:?}"),"n/a");
+      continue;
+    }
+    let start = Instant::now();
+    let cipher = ecb_encrypt(&aes,&data);
+    let speed = mib as f64/start.elapsed().as_secs_f64();
+    assert_eq!(cipher,reference);
+    if backend == Backend::Table {
+      table_speed = speed;
+    }
+    println!("{:<14}{:>10.1}{:>9.2}x",format!("{backend:?}"),speed,speed/table_speed);
+  }
+}
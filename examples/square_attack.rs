@@ -0,0 +1,32 @@
+/*
+Square attack demonstrator on 4-round AES-128
+cargo run --example square_attack [<32 hex digit key>]
+*/
+use std::env;
+use aes::research::ReducedRoundAes;
+use aes::square::square_attack;
+//
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+//
+fn main(){
+  let hex = env::args().nth(1).unwrap_or("000102030405060708090a0b0c0d0e0f".to_string());
+  if hex.len() != 32 {
+    println!("the key must be 32 hex digits");
+    return;
+  }
+  let key:Vec<u8> = (0..32).step_by(2).map(|i| u8::from_str_radix(&hex[i..i+2],16).expect("invalid hex digit")).collect();
+  // the secret key is only used inside the oracle
+  let oracle = ReducedRoundAes::new_insecure(&key,4,false).unwrap();
+  let mut queries = 0;
+  match square_attack(|block| { queries += 1; oracle.encrypt_block(block) },16) {
+    Some((round_key, master_key)) => {
+      println!("chosen plaintexts: {queries}");
+      println!("round 4 key:       {}",to_hex(&round_key));
+      println!("master key:        {}",to_hex(&master_key));
+      println!("{}", if master_key == key { "key recovered" } else { "wrong key" });
+    },
+    None => println!("no unique key after {queries} chosen plaintexts"),
+  }
+}
@@ -0,0 +1,107 @@
+/*
+no_std harness for the aes crate
+Built without the std feature and without alloc, run() checks the block
+cipher, the key schedules and the in place modes against published
+vectors on stack buffers only. cargo test -p aes-nostd-test runs it on the host,
+under cargo test --workspace the std feature of aes is unified in and is on
+*/
+#![no_std]
+use aes::ctr::ctr_xor_in_place;
+use aes::ecb::{ecb_decrypt_padded, ecb_encrypt_in_place, ecb_encrypt_padded};
+use aes::gcm::{gcm_decrypt_in_place, gcm_encrypt_in_place};
+use aes::ghash::{Polyval, UniversalHash};
+use aes::onthefly::OnTheFlyKey;
+use aes::xts::{xts_decrypt_in_place, xts_encrypt_in_place};
+use aes::{AesKey, Backend};
+//
+const BACKENDS: [Backend; 6] = [Backend::Table, Backend::ConstantTime, Backend::Bitsliced,
+    Backend::AesNi, Backend::TTable, Backend::Vpaes];
+//
+fn hex<const N: usize>(s: &str) -> [u8; N] {
+  let s = s.as_bytes();
+  let digit = |c: u8| (c as char).to_digit(16).unwrap() as u8;
+  core::array::from_fn(|i| digit(s[2*i]) << 4 | digit(s[2*i+1]))
+}
+//
+fn check(ok: bool, error: &'static str) -> Result<(), &'static str> {
+  if ok { Ok(()) } else { Err(error) }
+}
+// FIPS-197 appendix C on every backend and on the on-the-fly schedule
+fn block_cipher() -> Result<(), &'static str> {
+  let plain: [u8;16] = hex("00112233445566778899aabbccddeeff");
+  let key: [u8;32] = hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+  let expected: [[u8;16];3] = [hex("69c4e0d86a7b0430d8cdb78070b4c55a"), hex("dda97ca4864cdfe06eaf70a0ec0d7191"),
+    hex("8ea2b7ca516745bfeafc49904b496089")];
+  for (len, cipher) in [16, 24, 32].into_iter().zip(expected) {
+    for backend in BACKENDS {
+      let aes = AesKey::with_backend(&key[..len],backend)?;
+      check(aes.encrypt_block(plain) == cipher, "encrypt_block")?;
+      check(aes.decrypt_block(cipher) == plain, "decrypt_block")?;
+      let mut blocks = [plain; 9];
+      aes.encrypt_blocks(&mut blocks);
+      check(blocks == [cipher; 9], "encrypt_blocks")?;
+      aes.decrypt_blocks(&mut blocks);
+      check(blocks == [plain; 9], "decrypt_blocks")?;
+    }
+    let otf = OnTheFlyKey::new(&key[..len])?;
+    check(otf.encrypt_block(plain) == cipher, "on-the-fly encrypt")?;
+    check(otf.decrypt_block(cipher) == plain, "on-the-fly decrypt")?;
+  }
+  Ok(())
+}
+// NIST SP 800-38A F.1.1 and F.5.1, AES-128
+fn ecb_ctr() -> Result<(), &'static str> {
+  let aes = AesKey::with_backend(&hex::<16>("2b7e151628aed2a6abf7158809cf4f3c"),Backend::Bitsliced)?;
+  let plain: [u8;32] = hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+  let mut data = plain;
+  ecb_encrypt_in_place(&aes,&mut data)?;
+  check(data == hex("3ad77bb40d7a3660a89ecaf32466ef97f5d3d58503b9699de785895a96fdbaaf"), "ecb")?;
+  let mut buffer = [0u8;48];
+  buffer[..21].copy_from_slice(&plain[..21]);
+  let sealed = ecb_encrypt_padded(&aes,&mut buffer,21)?.len();
+  check(sealed == 32 && buffer[..16] == data[..16], "ecb padded")?;
+  check(ecb_decrypt_padded(&aes,&mut buffer[..sealed])? == &plain[..21], "ecb unpad")?;
+  let mut data = plain;
+  ctr_xor_in_place(&aes,hex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"),&mut data);
+  check(data == hex("874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff"), "ctr")
+}
+// GCM spec test case 2 with an all-zero key and IEEE 1619 XTS-AES-128 vector 2
+fn gcm_xts() -> Result<(), &'static str> {
+  let aes = AesKey::with_backend(&[0; 16],Backend::TTable)?;
+  let mut data = [0u8;16];
+  let tag = gcm_encrypt_in_place(&aes,&[0; 12],&[],&mut data)?;
+  check(data == hex("0388dace60b6a392f328c2b971b2fe78"), "gcm ciphertext")?;
+  check(tag == hex("ab6e47d42cec13bdf53a67b21257bddf"), "gcm tag")?;
+  check(gcm_decrypt_in_place(&aes,&[0; 12],&[1],&mut data,&tag).is_err(), "gcm forged aad")?;
+  gcm_decrypt_in_place(&aes,&[0; 12],&[],&mut data,&tag)?;
+  check(data == [0; 16], "gcm decrypt")?;
+  let (data_key, tweak_key) = (AesKey::new(&[0x11; 16])?, AesKey::new(&[0x22; 16])?);
+  let tweak = 0x33_3333_3333u128.to_le_bytes();
+  let mut data = [0x44u8;32];
+  xts_encrypt_in_place(&data_key,&tweak_key,tweak,&mut data)?;
+  check(data == hex("c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0"), "xts")?;
+  xts_decrypt_in_place(&data_key,&tweak_key,tweak,&mut data)?;
+  check(data == [0x44; 32], "xts decrypt")?;
+  check(xts_encrypt_in_place(&aes,&aes,tweak,&mut data).is_err(), "xts equal keys")
+}
+// RFC 8452 appendix A
+fn polyval() -> Result<(), &'static str> {
+  let mut polyval = Polyval::new(hex("25629347589242761d31f826ba4b757b"));
+  polyval.update(&[hex("4f4f95668c83dfb6401762bb2d01a262"), hex("d1a24ddd2721d006bbe45f20d3c9f362")]);
+  check(polyval.finalize() == hex("f7a3b47b846119fae5b7866cf5e5b77e"), "polyval")
+}
+// Every check, the name of the first one that fails
+pub fn run() -> Result<(), &'static str> {
+  block_cipher()?;
+  ecb_ctr()?;
+  gcm_xts()?;
+  polyval()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_run() {
+       assert_eq!(super::run(),Ok(()));
+    }
+}
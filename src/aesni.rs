@@ -0,0 +1,287 @@
+/*
+AES-NI backend
+Uses the x86 AES instructions, aeskeygenassist for the key schedule and
+aesimc for the equivalent inverse cipher keys, only when the CPU has them
+(available), AesKey falls back to the table backend otherwise
+*/
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+use crate::AesKey;
+//
+type RoundKeys = [[[u8; 4] ;4]; 15];
+//
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "std"))]
+pub fn available() -> bool {
+  is_x86_feature_detected!("aes") && is_x86_feature_detected!("sse2")
+}
+// Without std there is no run time detection, only what the target enables
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "std")))]
+pub fn available() -> bool {
+  cfg!(target_feature = "aes") && cfg!(target_feature = "sse2")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn available() -> bool {
+  false
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn load(bytes: &[[u8;4];4]) -> __m128i {
+  // SAFETY: 16 readable bytes, loadu has no alignment requirement
+  unsafe { _mm_loadu_si128(bytes.as_ptr() as *const __m128i) }
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn store(value: __m128i) -> [u8;16] {
+  let mut bytes:[u8;16] = [0;16];
+  // SAFETY: 16 writable bytes, storeu has no alignment requirement
+  unsafe { _mm_storeu_si128(bytes.as_mut_ptr() as *mut __m128i, value) };
+  bytes
+}
+// RotWord(SubWord(w)) ^ rcon from the top lane of aeskeygenassist, rcon
+// has to be an immediate
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn sub_rot_word(w: [u8;4], round: usize) -> [u8;4] {
+  let v = _mm_set_epi32(i32::from_le_bytes(w),0,0,0);
+  let assist = match round {
+    1 => _mm_aeskeygenassist_si128::<0x01>(v),
+    2 => _mm_aeskeygenassist_si128::<0x02>(v),
+    3 => _mm_aeskeygenassist_si128::<0x04>(v),
+    4 => _mm_aeskeygenassist_si128::<0x08>(v),
+    5 => _mm_aeskeygenassist_si128::<0x10>(v),
+    6 => _mm_aeskeygenassist_si128::<0x20>(v),
+    7 => _mm_aeskeygenassist_si128::<0x40>(v),
+    8 => _mm_aeskeygenassist_si128::<0x80>(v),
+    9 => _mm_aeskeygenassist_si128::<0x1b>(v),
+    10 => _mm_aeskeygenassist_si128::<0x36>(v),
+    // SubWord only, from lane 2
+    _ => return (_mm_cvtsi128_si32(_mm_shuffle_epi32::<0xaa>(_mm_aeskeygenassist_si128::<0x00>(v))) as u32).to_le_bytes(),
+  };
+  (_mm_cvtsi128_si32(_mm_shuffle_epi32::<0xff>(assist)) as u32).to_le_bytes()
+}
+// Encryption and equivalent inverse cipher schedules, like expand_key
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn expand_key_ni(key: &[u8]) -> (RoundKeys, RoundKeys) {
+  let nk = key.len()/4;
+  let nr = nk+6;
+  let mut w:[[u8; 4]; 60] = [[0; 4]; 60];
+  for i in 0..nk {
+    w[i] = [key[4*i], key[4*i+1], key[4*i+2], key[4*i+3]];
+  }
+  for i in nk..4*(nr+1) {
+    let mut tmp = w[i-1];
+    if i%nk == 0 {
+      tmp = sub_rot_word(tmp,i/nk);
+    } else if nk > 6 && i%nk == 4 {
+      tmp = sub_rot_word(tmp,0);
+    }
+    for x in 0..4 {
+      w[i][x] = w[i-nk][x] ^ tmp[x];
+    }
+  }
+  let mut enc:RoundKeys = [[[0; 4]; 4]; 15];
+  let mut dec:RoundKeys = [[[0; 4]; 4]; 15];
+  for x in 0..=nr {
+    enc[x].copy_from_slice(&w[4*x..4*x+4]);
+    let imc = if x == 0 || x == nr { load(&enc[x]) } else { _mm_aesimc_si128(load(&enc[x])) };
+    for (column, bytes) in dec[x].iter_mut().zip(store(imc).chunks_exact(4)) {
+      column.copy_from_slice(bytes);
+    }
+  }
+  (enc, dec)
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn encrypt_ni(block: [u8;16], keys: &RoundKeys, nr: usize) -> [u8;16] {
+  // SAFETY: 16 readable bytes
+  let mut state = unsafe { _mm_loadu_si128(block.as_ptr() as *const __m128i) };
+  state = _mm_xor_si128(state,load(&keys[0]));
+  for key in keys.iter().take(nr).skip(1) {
+    state = _mm_aesenc_si128(state,load(key));
+  }
+  store(_mm_aesenclast_si128(state,load(&keys[nr])))
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn decrypt_ni(block: [u8;16], keys: &RoundKeys, nr: usize) -> [u8;16] {
+  // SAFETY: 16 readable bytes
+  let mut state = unsafe { _mm_loadu_si128(block.as_ptr() as *const __m128i) };
+  state = _mm_xor_si128(state,load(&keys[nr]));
+  for key in keys.iter().take(nr).skip(1).rev() {
+    state = _mm_aesdec_si128(state,load(key));
+  }
+  store(_mm_aesdeclast_si128(state,load(&keys[0])))
+}
+// Up to eight blocks, every round is issued for all of them before the
+// next one so the aesenc latencies overlap
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn encrypt_blocks_ni(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  let mut state = [_mm_setzero_si128(); 8];
+  let k = load(&keys[0]);
+  for (s, block) in state.iter_mut().zip(blocks.iter()) {
+    // SAFETY: 16 readable bytes
+    *s = _mm_xor_si128(unsafe { _mm_loadu_si128(block.as_ptr() as *const __m128i) },k);
+  }
+  for key in keys.iter().take(nr).skip(1) {
+    let k = load(key);
+    for s in state[..blocks.len()].iter_mut() {
+      *s = _mm_aesenc_si128(*s,k);
+    }
+  }
+  let k = load(&keys[nr]);
+  for (s, block) in state.iter().zip(blocks.iter_mut()) {
+    *block = store(_mm_aesenclast_si128(*s,k));
+  }
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn decrypt_blocks_ni(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  let mut state = [_mm_setzero_si128(); 8];
+  let k = load(&keys[nr]);
+  for (s, block) in state.iter_mut().zip(blocks.iter()) {
+    // SAFETY: 16 readable bytes
+    *s = _mm_xor_si128(unsafe { _mm_loadu_si128(block.as_ptr() as *const __m128i) },k);
+  }
+  for key in keys.iter().take(nr).skip(1).rev() {
+    let k = load(key);
+    for s in state[..blocks.len()].iter_mut() {
+      *s = _mm_aesdec_si128(*s,k);
+    }
+  }
+  let k = load(&keys[0]);
+  for (s, block) in state.iter().zip(blocks.iter_mut()) {
+    *block = store(_mm_aesdeclast_si128(*s,k));
+  }
+}
+// Block i with its own schedule keys[i], the rounds of every lane run
+// together, shorter schedules finish early
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn encrypt_multi_ni(blocks: &mut [[u8;16]], keys: &[&AesKey]) {
+  let mut state = [_mm_setzero_si128(); 8];
+  for ((s, block), key) in state.iter_mut().zip(blocks.iter()).zip(keys.iter()) {
+    // SAFETY: 16 readable bytes
+    *s = _mm_xor_si128(unsafe { _mm_loadu_si128(block.as_ptr() as *const __m128i) },load(&key.enc[0]));
+  }
+  let rounds = keys.iter().map(|key| key.rounds).max().unwrap_or(0);
+  for round in 1..=rounds {
+    for (s, key) in state.iter_mut().zip(keys.iter()) {
+      if round < key.rounds {
+        *s = _mm_aesenc_si128(*s,load(&key.enc[round]));
+      } else if round == key.rounds {
+        *s = _mm_aesenclast_si128(*s,load(&key.enc[round]));
+      }
+    }
+  }
+  for (s, block) in state.iter().zip(blocks.iter_mut()) {
+    *block = store(*s);
+  }
+}
+// The safe functions panic when the CPU has no AES-NI, check available first
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn expand_key(key: &[u8]) -> (RoundKeys, RoundKeys) {
+  assert!(available(),"AES-NI is not available");
+  // SAFETY: the CPU features were detected above
+  unsafe { expand_key_ni(key) }
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn encrypt_block(block: [u8;16], keys: &RoundKeys, nr: usize) -> [u8;16] {
+  assert!(available(),"AES-NI is not available");
+  // SAFETY: the CPU features were detected above
+  unsafe { encrypt_ni(block,keys,nr) }
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn decrypt_block(block: [u8;16], keys: &RoundKeys, nr: usize) -> [u8;16] {
+  assert!(available(),"AES-NI is not available");
+  // SAFETY: the CPU features were detected above
+  unsafe { decrypt_ni(block,keys,nr) }
+}
+// At most eight blocks in place
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn encrypt_blocks(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  assert!(available(),"AES-NI is not available");
+  assert!(blocks.len() <= 8,"at most eight blocks");
+  // SAFETY: the CPU features were detected above
+  unsafe { encrypt_blocks_ni(blocks,keys,nr) }
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn decrypt_blocks(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  assert!(available(),"AES-NI is not available");
+  assert!(blocks.len() <= 8,"at most eight blocks");
+  // SAFETY: the CPU features were detected above
+  unsafe { decrypt_blocks_ni(blocks,keys,nr) }
+}
+// At most eight blocks, each with its own key
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn encrypt_blocks_multi(blocks: &mut [[u8;16]], keys: &[&AesKey]) {
+  assert!(available(),"AES-NI is not available");
+  assert!(blocks.len() <= 8 && keys.len() == blocks.len(),"one key for each of at most eight blocks");
+  // SAFETY: the CPU features were detected above
+  unsafe { encrypt_multi_ni(blocks,keys) }
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn expand_key(_key: &[u8]) -> (RoundKeys, RoundKeys) {
+  panic!("AES-NI is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn encrypt_block(_block: [u8;16], _keys: &RoundKeys, _nr: usize) -> [u8;16] {
+  panic!("AES-NI is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn decrypt_block(_block: [u8;16], _keys: &RoundKeys, _nr: usize) -> [u8;16] {
+  panic!("AES-NI is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn encrypt_blocks_multi(_blocks: &mut [[u8;16]], _keys: &[&AesKey]) {
+  panic!("AES-NI is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn encrypt_blocks(_blocks: &mut [[u8;16]], _keys: &RoundKeys, _nr: usize) {
+  panic!("AES-NI is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn decrypt_blocks(_blocks: &mut [[u8;16]], _keys: &RoundKeys, _nr: usize) {
+  panic!("AES-NI is not available")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{expand_key as table_expand_key, AesKey, Backend};
+    #[test]
+    fn test_aesni_matches_table() {
+       if !available() {
+         return;
+       }
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       for len in [16,24,32] {
+         let key:Vec<u8> = (0..len as u8).map(|b| b.wrapping_mul(29)^0xa5).collect();
+         let (enc, dec) = expand_key(&key);
+         assert_eq!(enc,table_expand_key::<4>(&key,false));
+         assert_eq!(dec,table_expand_key::<4>(&key,true));
+         let table = AesKey::new(&key).unwrap();
+         let ni = AesKey::with_backend(&key,Backend::AesNi).unwrap();
+         assert_eq!(ni.backend,Backend::AesNi);
+         let cipher = ni.encrypt_block(plain);
+         assert_eq!(cipher,table.encrypt_block(plain));
+         assert_eq!(ni.decrypt_block(cipher),plain);
+       }
+    }
+}
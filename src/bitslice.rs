@@ -0,0 +1,315 @@
+/*
+64-bit bitsliced AES, up to four blocks at once
+Plane i holds bit i of all 64 state bytes, byte j of block b sits at bit
+16*b + j, so a column is a nibble and ShiftRows, MixColumns and the
+S-box (the Boyar-Peralta circuit) are shifts, masks and logic gates
+without any secret dependent memory access. The round keys are converted
+once per AesKey, and the linear layers are always inlined so the state
+stays in registers
+*/
+use crate::AesKey;
+//
+type Planes = [u64; 8];
+// 16-bit mask of one block repeated in the four lanes
+const fn lanes(mask: u64) -> u64 {
+  mask * 0x0001_0001_0001_0001
+}
+//
+// Bit i of byte m goes to bit m of byte i, an 8x8 bit matrix transpose
+fn transpose(mut x: u64) -> u64 {
+  let t = (x ^ (x >> 7)) & 0x00aa_00aa_00aa_00aa;
+  x ^= t ^ (t << 7);
+  let t = (x ^ (x >> 14)) & 0x0000_cccc_0000_cccc;
+  x ^= t ^ (t << 14);
+  let t = (x ^ (x >> 28)) & 0x0000_0000_f0f0_f0f0;
+  x ^ t ^ (t << 28)
+}
+// Word k holds the bits of bytes 8k..8k+8 transposed, byte k of plane i
+// is byte i of word k
+fn to_planes(blocks: &[[u8;16]]) -> Planes {
+  let mut words:[u64; 8] = [0; 8];
+  for (b, block) in blocks.iter().enumerate() {
+    for (h, half) in block.chunks_exact(8).enumerate() {
+      words[2*b+h] = transpose(u64::from_le_bytes(half.try_into().unwrap()));
+    }
+  }
+  core::array::from_fn(|i| words.iter().enumerate().fold(0, |plane, (k, word)| plane | ((word >> (8*i)) & 0xff) << (8*k)))
+}
+//
+fn from_planes(planes: &Planes, blocks: &mut [[u8;16]]) {
+  for (b, block) in blocks.iter_mut().enumerate() {
+    for (h, half) in block.chunks_exact_mut(8).enumerate() {
+      let k = 2*b+h;
+      let word = planes.iter().enumerate().fold(0, |word, (i, plane)| word | ((plane >> (8*k)) & 0xff) << (8*i));
+      half.copy_from_slice(&transpose(word).to_le_bytes());
+    }
+  }
+}
+// Boyar and Peralta's 113 gate S-box circuit (32 AND), with u0 the most
+// significant input bit and without the NOT gates, so it returns S(x) ^ 0x63
+fn sbox_circuit(planes: &Planes) -> Planes {
+  let (u0, u1, u2, u3) = (planes[7], planes[6], planes[5], planes[4]);
+  let (u4, u5, u6, u7) = (planes[3], planes[2], planes[1], planes[0]);
+  let y14 = u3 ^ u5;
+  let y13 = u0 ^ u6;
+  let y12 = y13 ^ y14;
+  let t1 = u4 ^ y12;
+  let y15 = t1 ^ u5;
+  let t2 = y12 & y15;
+  let y6 = y15 ^ u7;
+  let y20 = t1 ^ u1;
+  let y9 = u0 ^ u3;
+  let y11 = y20 ^ y9;
+  let t12 = y9 & y11;
+  let y7 = u7 ^ y11;
+  let y8 = u0 ^ u5;
+  let t0 = u1 ^ u2;
+  let y10 = y15 ^ t0;
+  let y17 = y10 ^ y11;
+  let t13 = y14 & y17;
+  let t14 = t13 ^ t12;
+  let y19 = y10 ^ y8;
+  let t15 = y8 & y10;
+  let t16 = t15 ^ t12;
+  let y16 = t0 ^ y11;
+  let y21 = y13 ^ y16;
+  let t7 = y13 & y16;
+  let y18 = u0 ^ y16;
+  let y1 = t0 ^ u7;
+  let y4 = y1 ^ u3;
+  let t5 = y4 & u7;
+  let t6 = t5 ^ t2;
+  let t18 = t6 ^ t16;
+  let t22 = t18 ^ y19;
+  let y2 = y1 ^ u0;
+  let t10 = y2 & y7;
+  let t11 = t10 ^ t7;
+  let t20 = t11 ^ t16;
+  let t24 = t20 ^ y18;
+  let y5 = y1 ^ u6;
+  let t8 = y5 & y1;
+  let t9 = t8 ^ t7;
+  let t19 = t9 ^ t14;
+  let t23 = t19 ^ y21;
+  let y3 = y5 ^ y8;
+  let t3 = y3 & y6;
+  let t4 = t3 ^ t2;
+  let t17 = t4 ^ y20;
+  let t21 = t17 ^ t14;
+  let t26 = t21 & t23;
+  let t27 = t24 ^ t26;
+  let t31 = t22 ^ t26;
+  let t25 = t21 ^ t22;
+  let t28 = t25 & t27;
+  let t29 = t28 ^ t22;
+  let z14 = t29 & y2;
+  let z5 = t29 & y7;
+  let t30 = t23 ^ t24;
+  let t32 = t31 & t30;
+  let t33 = t32 ^ t24;
+  let t35 = t27 ^ t33;
+  let t36 = t24 & t35;
+  let t38 = t27 ^ t36;
+  let t39 = t29 & t38;
+  let t40 = t25 ^ t39;
+  let t43 = t29 ^ t40;
+  let z3 = t43 & y16;
+  let tc12 = z3 ^ z5;
+  let z12 = t43 & y13;
+  let z13 = t40 & y5;
+  let z4 = t40 & y1;
+  let tc6 = z3 ^ z4;
+  let t34 = t23 ^ t33;
+  let t37 = t36 ^ t34;
+  let t41 = t40 ^ t37;
+  let z8 = t41 & y10;
+  let z17 = t41 & y8;
+  let t44 = t33 ^ t37;
+  let z0 = t44 & y15;
+  let z9 = t44 & y12;
+  let z10 = t37 & y3;
+  let z1 = t37 & y6;
+  let tc5 = z1 ^ z0;
+  let tc11 = tc6 ^ tc5;
+  let z11 = t33 & y4;
+  let t42 = t29 ^ t33;
+  let t45 = t42 ^ t41;
+  let z7 = t45 & y17;
+  let tc8 = z7 ^ tc6;
+  let z16 = t45 & y14;
+  let z6 = t42 & y11;
+  let tc16 = z6 ^ tc8;
+  let z15 = t42 & y9;
+  let tc20 = z15 ^ tc16;
+  let tc1 = z15 ^ z16;
+  let tc2 = z10 ^ tc1;
+  let tc21 = tc2 ^ z11;
+  let tc3 = z9 ^ tc2;
+  let s0 = tc3 ^ tc16;
+  let s3 = tc3 ^ tc11;
+  let s1 = s3 ^ tc16;
+  let tc13 = z13 ^ tc1;
+  let z2 = t33 & u7;
+  let tc4 = z0 ^ z2;
+  let tc7 = z12 ^ tc4;
+  let tc9 = z8 ^ tc7;
+  let tc10 = tc8 ^ tc9;
+  let tc17 = z14 ^ tc10;
+  let s5 = tc21 ^ tc17;
+  let tc26 = tc17 ^ tc20;
+  let s2 = tc26 ^ z17;
+  let tc14 = tc4 ^ tc12;
+  let tc18 = tc13 ^ tc14;
+  let s6 = tc10 ^ tc18;
+  let s7 = z12 ^ tc18;
+  let s4 = tc14 ^ s3;
+  [s7, s6, s5, s4, s3, s2, s1, s0]
+}
+//
+fn sub_bytes(s: &Planes) -> Planes {
+  let mut result = sbox_circuit(s);
+  for i in [0, 1, 5, 6] {
+    result[i] = !result[i];
+  }
+  result
+}
+// Linear part of the inverse affine transform
+fn inv_affine(s: &Planes) -> Planes {
+  core::array::from_fn(|i| s[(i+2)%8] ^ s[(i+5)%8] ^ s[(i+7)%8])
+}
+// x^-1 = A^-1(S(x) ^ 0x63) and S^-1(x) = (A^-1(x) ^ 0x05)^-1
+fn inv_sub_bytes(s: &Planes) -> Planes {
+  let mut b = inv_affine(s);
+  for i in [0, 2] {
+    b[i] = !b[i];
+  }
+  inv_affine(&sbox_circuit(&b))
+}
+// ShiftRows moves row r left by r columns, right by 4r bits in the lane
+#[inline(always)]
+fn shift_rows(s: &Planes) -> Planes {
+  s.map(|x| (x & lanes(0x1111)) | ((x >> 4) & lanes(0x0222)) | ((x << 12) & lanes(0x2000))
+    | ((x >> 8) & lanes(0x0044)) | ((x << 8) & lanes(0x4400)) | ((x >> 12) & lanes(0x0008)) | ((x << 4) & lanes(0x8880)))
+}
+// Row r moves right by r columns
+#[inline(always)]
+fn inv_shift_rows(s: &Planes) -> Planes {
+  s.map(|x| (x & lanes(0x1111)) | ((x >> 12) & lanes(0x0002)) | ((x << 4) & lanes(0x2220))
+    | ((x >> 8) & lanes(0x0044)) | ((x << 8) & lanes(0x4400)) | ((x >> 4) & lanes(0x0888)) | ((x << 12) & lanes(0x8000)))
+}
+// byte r of every column takes the value of byte r+1
+fn rotate_column_1(x: u64) -> u64 {
+  ((x >> 1) & lanes(0x7777)) | ((x << 3) & lanes(0x8888))
+}
+// byte r takes the value of byte r+2
+fn rotate_column_2(x: u64) -> u64 {
+  ((x >> 2) & lanes(0x3333)) | ((x << 2) & lanes(0xcccc))
+}
+//
+#[inline(always)]
+fn xtime(a: &Planes) -> Planes {
+  [a[7], a[0]^a[7], a[1], a[2]^a[7], a[3]^a[7], a[4], a[5], a[6]]
+}
+// 2a(r) + 3a(r+1) + a(r+2) + a(r+3) = 2t(r) + a(r+1) + t(r+2) with t(r) = a(r) + a(r+1)
+#[inline(always)]
+fn mix_columns(s: &Planes) -> Planes {
+  let t:Planes = s.map(|x| x ^ rotate_column_1(x));
+  let t2 = xtime(&t);
+  core::array::from_fn(|i| t2[i] ^ rotate_column_1(s[i]) ^ rotate_column_2(t[i]))
+}
+// (4x^2 + 5) then MixColumns
+#[inline(always)]
+fn inv_mix_columns(s: &Planes) -> Planes {
+  let u = xtime(&xtime(&s.map(|x| x ^ rotate_column_2(x))));
+  mix_columns(&core::array::from_fn(|i| s[i] ^ u[i]))
+}
+//
+fn add_round_key(s: &mut Planes, key: &Planes) {
+  for (plane, k) in s.iter_mut().zip(key.iter()) {
+    *plane ^= k;
+  }
+}
+// Encryption round keys copied into all four block lanes, computed once
+// when the AesKey is built
+pub fn round_keys(enc: &[[[u8; 4] ;4]; 15]) -> [Planes; 15] {
+  enc.map(|key| to_planes(&[key.as_flattened().try_into().unwrap(); 4]))
+}
+// Lane b uses the round keys of keys[b], Bitsliced keys with the same
+// number of rounds
+pub fn round_keys_multi(keys: &[&AesKey]) -> [Planes; 15] {
+  assert!(keys.len() <= 4 && keys.iter().all(|key| key.rounds == keys[0].rounds),"at most four keys of one size");
+  let mut planes:[Planes; 15] = [[0; 8]; 15];
+  for (b, key) in keys.iter().enumerate() {
+    let lane = 0xffff << (16*b);
+    for (p, k) in planes.iter_mut().zip(key.planes.iter()) {
+      for (plane, key_plane) in p.iter_mut().zip(k.iter()) {
+        *plane |= key_plane & lane;
+      }
+    }
+  }
+  planes
+}
+// Encrypts up to four blocks in place
+pub fn encrypt_blocks(keys: &[Planes; 15], rounds: usize, blocks: &mut [[u8;16]]) {
+  assert!(blocks.len() <= 4,"at most four blocks");
+  let mut s = to_planes(blocks);
+  add_round_key(&mut s,&keys[0]);
+  for (i, key) in keys.iter().enumerate().take(rounds+1).skip(1) {
+    s = shift_rows(&sub_bytes(&s));
+    if i != rounds {
+      s = mix_columns(&s);
+    }
+    add_round_key(&mut s,key);
+  }
+  from_planes(&s,blocks);
+}
+// Straightforward inverse cipher with the encryption round keys
+pub fn decrypt_blocks(keys: &[Planes; 15], rounds: usize, blocks: &mut [[u8;16]]) {
+  assert!(blocks.len() <= 4,"at most four blocks");
+  let mut s = to_planes(blocks);
+  add_round_key(&mut s,&keys[rounds]);
+  for i in (0..rounds).rev() {
+    s = inv_sub_bytes(&inv_shift_rows(&s));
+    add_round_key(&mut s,&keys[i]);
+    if i != 0 {
+      s = inv_mix_columns(&s);
+    }
+  }
+  from_planes(&s,blocks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes_encrypt;
+    use crate::gf::{INV_SBOX, SBOX};
+    #[test]
+    fn test_sbox_circuit() {
+       let bytes:[[u8;16]; 16] = core::array::from_fn(|b| core::array::from_fn(|j| (16*b+j) as u8));
+       for group in bytes.chunks(4) {
+         let planes = to_planes(group);
+         let mut blocks = [[0u8;16]; 4];
+         from_planes(&planes,&mut blocks);
+         assert_eq!(blocks,group);
+         from_planes(&sub_bytes(&planes),&mut blocks);
+         assert_eq!(blocks.as_flattened(),group.as_flattened().iter().map(|x| SBOX[*x as usize]).collect::<Vec<u8>>());
+         from_planes(&inv_sub_bytes(&planes),&mut blocks);
+         assert_eq!(blocks.as_flattened(),group.as_flattened().iter().map(|x| INV_SBOX[*x as usize]).collect::<Vec<u8>>());
+       }
+    }
+    #[test]
+    fn test_bitsliced_matches_aes_encrypt() {
+       let key:[u8;32] = core::array::from_fn(|i| (i*7) as u8);
+       let input:Vec<u8> = (0..64).map(|i| (i*13+5) as u8).collect();
+       let reference = aes_encrypt(input.clone(),key,input.len()-1);
+       let aes = AesKey::new(&key).unwrap();
+       let keys = round_keys(&aes.enc);
+       for n in 1..=4 {
+         let mut blocks:Vec<[u8;16]> = input[..16*n].chunks_exact(16).map(|b| b.try_into().unwrap()).collect();
+         encrypt_blocks(&keys,aes.rounds,&mut blocks);
+         assert_eq!(blocks.concat(),reference[..16*n]);
+         decrypt_blocks(&keys,aes.rounds,&mut blocks);
+         assert_eq!(blocks.concat(),input[..16*n]);
+       }
+    }
+}
@@ -0,0 +1,65 @@
+/*
+Cipher block chaining mode
+NIST SP 800-38A, with PKCS#7 padding
+*/
+use std::convert::TryInto;
+use crate::AesKey;
+//
+fn xor_block(a:[u8;16], b:[u8;16]) -> [u8;16]{
+  let mut result:[u8;16]=[0;16];
+  for i in 0..16 {
+    result[i]=a[i]^b[i];
+  }
+  result
+}
+// C(i) = CIPH(P(i) ^ C(i-1)), C(0) = IV
+pub fn cbc_encrypt(key:&AesKey, iv:[u8;16], input:&[u8]) -> Vec<u8>{
+  let pad = 16-input.len()%16;
+  let mut padded:Vec<u8> = input.to_vec();
+  padded.extend(std::iter::repeat_n(pad as u8,pad));
+  let mut result:Vec<u8> = vec![];
+  let mut previous = iv;
+  for block in padded.chunks_exact(16) {
+    previous = key.encrypt_block(xor_block(block.try_into().unwrap(),previous));
+    result.extend_from_slice(&previous);
+  }
+  result
+}
+// P(i) = INVCIPH(C(i)) ^ C(i-1), then removes the padding
+pub fn cbc_decrypt(key:&AesKey, iv:[u8;16], input:&[u8]) -> Result<Vec<u8>, &'static str>{
+  if input.is_empty() || !input.len().is_multiple_of(16) {
+    return Err("ciphertext is not a whole number of blocks");
+  }
+  let mut result:Vec<u8> = vec![];
+  let mut previous = iv;
+  for block in input.chunks_exact(16) {
+    let block:[u8;16] = block.try_into().unwrap();
+    result.extend_from_slice(&xor_block(key.decrypt_block(block),previous));
+    previous = block;
+  }
+  let pad = result[result.len()-1] as usize;
+  if pad == 0 || pad > 16 || result[result.len()-pad..].iter().any(|b| *b as usize != pad) {
+    return Err("invalid padding");
+  }
+  result.truncate(result.len()-pad);
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_cbc() {
+       // NIST SP 800-38A F.2.5 CBC-AES256.Encrypt, first two blocks
+       let key:[u8;32]=[0x60,0x3d,0xeb,0x10,0x15,0xca,0x71,0xbe,0x2b,0x73,0xae,0xf0,0x85,0x7d,0x77,0x81,0x1f,0x35,0x2c,0x07,0x3b,0x61,0x08,0xd7,0x2d,0x98,0x10,0xa3,0x09,0x14,0xdf,0xf4];
+       let iv:[u8;16]=[0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15];
+       let plain:[u8;32]=[0x6b,0xc1,0xbe,0xe2,0x2e,0x40,0x9f,0x96,0xe9,0x3d,0x7e,0x11,0x73,0x93,0x17,0x2a,0xae,0x2d,0x8a,0x57,0x1e,0x03,0xac,0x9c,0x9e,0xb7,0x6f,0xac,0x45,0xaf,0x8e,0x51];
+       let expected:[u8;32]=[0xf5,0x8c,0x4c,0x04,0xd6,0xe5,0xf1,0xba,0x77,0x9e,0xab,0xfb,0x5f,0x7b,0xfb,0xd6,0x9c,0xfc,0x4e,0x96,0x7e,0xdb,0x80,0x8d,0x67,0x9f,0x77,0x7b,0xc6,0x70,0x2c,0x7d];
+       let aes = AesKey::new(&key).unwrap();
+       let cipher = cbc_encrypt(&aes,iv,&plain);
+       assert_eq!(cipher.len(),48);
+       assert_eq!(cipher[..32],expected);
+       assert_eq!(cbc_decrypt(&aes,iv,&cipher).unwrap(),plain);
+       assert!(cbc_decrypt(&aes,iv,&cipher[..32]).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+/*
+Authenticated encryption with AES-CBC and HMAC-SHA2
+RFC 7518 section 5.2, A128CBC-HS256, A192CBC-HS384 and A256CBC-HS512
+The key is MAC_KEY || ENC_KEY, the tag is the truncated
+HMAC(MAC_KEY, A || IV || E || AL)
+*/
+use crate::AesKey;
+use crate::cbc::{cbc_encrypt, cbc_decrypt};
+use crate::hmac::{hmac_sha256, hmac_sha384, hmac_sha512, verify_tag};
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CbcHmacAlg {
+    A128CbcHs256,
+    A192CbcHs384,
+    A256CbcHs512,
+}
+//
+impl CbcHmacAlg {
+    // length of MAC_KEY, of ENC_KEY and of the tag
+    fn half_key_len(&self) -> usize {
+        match self {
+            CbcHmacAlg::A128CbcHs256 => 16,
+            CbcHmacAlg::A192CbcHs384 => 24,
+            CbcHmacAlg::A256CbcHs512 => 32,
+        }
+    }
+    fn mac(&self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        let tag = match self {
+            CbcHmacAlg::A128CbcHs256 => hmac_sha256(key,message).to_vec(),
+            CbcHmacAlg::A192CbcHs384 => hmac_sha384(key,message).to_vec(),
+            CbcHmacAlg::A256CbcHs512 => hmac_sha512(key,message).to_vec(),
+        };
+        tag[..self.half_key_len()].to_vec()
+    }
+}
+//
+fn mac_input(aad:&[u8], iv:[u8;16], ciphertext:&[u8]) -> Vec<u8>{
+  let al = (aad.len() as u64)*8;
+  let mut input:Vec<u8> = aad.to_vec();
+  input.extend_from_slice(&iv);
+  input.extend_from_slice(ciphertext);
+  input.extend_from_slice(&al.to_be_bytes());
+  input
+}
+//
+fn split_key(alg:CbcHmacAlg, key:&[u8]) -> Result<(&[u8], AesKey), &'static str>{
+  let n = alg.half_key_len();
+  if key.len() != 2*n {
+    return Err("wrong key length for the algorithm");
+  }
+  Ok((&key[..n], AesKey::new(&key[n..])?))
+}
+// Returns the ciphertext and the authentication tag
+pub fn cbc_hmac_encrypt(alg:CbcHmacAlg, key:&[u8], iv:[u8;16], aad:&[u8], plaintext:&[u8]) -> Result<(Vec<u8>, Vec<u8>), &'static str>{
+  let (mac_key, enc_key) = split_key(alg,key)?;
+  let ciphertext = cbc_encrypt(&enc_key,iv,plaintext);
+  let tag = alg.mac(mac_key,&mac_input(aad,iv,&ciphertext));
+  Ok((ciphertext, tag))
+}
+// The tag is checked before any decryption
+pub fn cbc_hmac_decrypt(alg:CbcHmacAlg, key:&[u8], iv:[u8;16], aad:&[u8], ciphertext:&[u8], tag:&[u8]) -> Result<Vec<u8>, &'static str>{
+  let (mac_key, enc_key) = split_key(alg,key)?;
+  let expected = alg.mac(mac_key,&mac_input(aad,iv,ciphertext));
+  if !verify_tag(&expected,tag) {
+    return Err("authentication tag mismatch");
+  }
+  cbc_decrypt(&enc_key,iv,ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+    // RFC 7518 Appendix B
+    const PLAIN:&[u8] = b"A cipher system must not be required to be secret, and it must be able to fall into the hands of the enemy without inconvenience";
+    const AAD:&[u8] = b"The second principle of Auguste Kerckhoffs";
+    const IV:[u8;16]=[0x1a,0xf3,0x8c,0x2d,0xc2,0xb9,0x6f,0xfd,0xd8,0x66,0x94,0x09,0x23,0x41,0xbc,0x04];
+    fn key(len:u8) -> Vec<u8> {
+       (0..len).collect()
+    }
+    #[test]
+    fn test_a128cbc_hs256() {
+       let (cipher, tag) = cbc_hmac_encrypt(CbcHmacAlg::A128CbcHs256,&key(32),IV,AAD,PLAIN).unwrap();
+       assert_eq!(hex(&cipher),"c80edfa32ddf39d5ef00c0b468834279a2e46a1b8049f792f76bfe54b903a9c9a94ac9b47ad2655c5f10f9aef71427e2fc6f9b3f399a221489f16362c703233609d45ac69864e3321cf82935ac4096c86e133314c54019e8ca7980dfa4b9cf1b384c486f3a54c51078158ee5d79de59fbd34d848b3d69550a67646344427ade54b8851ffb598f7f80074b9473c82e2db");
+       assert_eq!(hex(&tag),"652c3fa36b0a7c5b3219fab3a30bc1c4");
+       assert_eq!(cbc_hmac_decrypt(CbcHmacAlg::A128CbcHs256,&key(32),IV,AAD,&cipher,&tag).unwrap(),PLAIN);
+    }
+    #[test]
+    fn test_a192cbc_hs384() {
+       let (cipher, tag) = cbc_hmac_encrypt(CbcHmacAlg::A192CbcHs384,&key(48),IV,AAD,PLAIN).unwrap();
+       assert_eq!(hex(&cipher),"ea65da6b59e61edb419be62d19712ae5d303eeb50052d0dfd6697f77224c8edb000d279bdc14c1072654bd30944230c657bed4ca0c9f4a8466f22b226d1746214bf8cfc2400add9f5126e479663fc90b3bed787a2f0ffcbf3904be2a641d5c2105bfe591bae23b1d7449e532eef60a9ac8bb6c6b01d35d49787bcd57ef484927f280adc91ac0c4e79c7b11efc60054e3");
+       assert_eq!(hex(&tag),"8490ac0e58949bfe51875d733f93ac2075168039ccc733d7");
+       assert_eq!(cbc_hmac_decrypt(CbcHmacAlg::A192CbcHs384,&key(48),IV,AAD,&cipher,&tag).unwrap(),PLAIN);
+    }
+    #[test]
+    fn test_a256cbc_hs512() {
+       let (cipher, tag) = cbc_hmac_encrypt(CbcHmacAlg::A256CbcHs512,&key(64),IV,AAD,PLAIN).unwrap();
+       assert_eq!(hex(&cipher),"4affaaadb78c31c5da4b1b590d10ffbd3dd8d5d302423526912da037ecbcc7bd822c301dd67c373bccb584ad3e9279c2e6d12a1374b77f077553df829410446b36ebd97066296ae6427ea75c2e0846a11a09ccf5370dc80bfecbad28c73f09b3a3b75e662a2594410ae496b2e2e6609e31e6e02cc837f053d21f37ff4f51950bbe2638d09dd7a4930930806d0703b1f6");
+       assert_eq!(hex(&tag),"4dd3b4c088a7f45c216839645b2012bf2e6269a8c56a816dbc1b267761955bc5");
+       assert_eq!(cbc_hmac_decrypt(CbcHmacAlg::A256CbcHs512,&key(64),IV,AAD,&cipher,&tag).unwrap(),PLAIN);
+    }
+    #[test]
+    fn test_cbc_hmac_rejects_tampering() {
+       let (mut cipher, tag) = cbc_hmac_encrypt(CbcHmacAlg::A256CbcHs512,&key(64),IV,AAD,PLAIN).unwrap();
+       assert!(cbc_hmac_decrypt(CbcHmacAlg::A256CbcHs512,&key(64),IV,b"other",&cipher,&tag).is_err());
+       cipher[0]^=1;
+       assert!(cbc_hmac_decrypt(CbcHmacAlg::A256CbcHs512,&key(64),IV,AAD,&cipher,&tag).is_err());
+       assert!(cbc_hmac_encrypt(CbcHmacAlg::A256CbcHs512,&key(32),IV,AAD,PLAIN).is_err());
+    }
+}
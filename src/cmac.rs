@@ -0,0 +1,77 @@
+/*
+AES-CMAC
+NIST SP 800-38B
+*/
+use crate::{encrypt_block, key_expansion};
+// Multiplication by x in GF(2^128), Rb = 0x87
+fn dbl(block:[u8;16]) -> [u8;16]{
+  let mut result:[u8;16]=[0;16];
+  for i in 0..15 {
+    result[i]=(block[i]<<1)|(block[i+1]>>7);
+  }
+  result[15]=(block[15]<<1)^(0x87*(block[0]>>7));
+  result
+}
+//
+fn xor_block(a:[u8;16], b:[u8;16]) -> [u8;16]{
+  let mut result:[u8;16]=[0;16];
+  for i in 0..16 {
+    result[i]=a[i]^b[i];
+  }
+  result
+}
+//
+fn subkeys(keys:[[[u8; 4] ;4]; 15]) -> ([u8;16],[u8;16]){
+  let l = encrypt_block([0;16],keys);
+  let k1 = dbl(l);
+  let k2 = dbl(k1);
+  (k1,k2)
+}
+// CMAC with an already expanded encryption key schedule
+pub fn cmac_expanded(keys:[[[u8; 4] ;4]; 15], message:&[u8]) -> [u8;16]{
+  let (k1,k2) = subkeys(keys);
+  let n = if message.is_empty() { 1 } else { message.len().div_ceil(16) };
+  let complete = !message.is_empty() && message.len().is_multiple_of(16);
+  let mut c:[u8;16]=[0;16];
+  for i in 0..n-1 {
+    let block:[u8;16] = message[16*i..16*i+16].try_into().unwrap();
+    c = encrypt_block(xor_block(c,block),keys);
+  }
+  let tail = &message[16*(n-1)..];
+  let mut last:[u8;16]=[0;16];
+  last[..tail.len()].copy_from_slice(tail);
+  let last = if complete {
+    xor_block(last,k1)
+  } else {
+    last[tail.len()]=0x80;
+    xor_block(last,k2)
+  };
+  encrypt_block(xor_block(c,last),keys)
+}
+//
+pub fn cmac(key:[u8;32], message:&[u8]) -> [u8;16]{
+  cmac_expanded(key_expansion(key,false),message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // NIST SP 800-38B Appendix D.3, CMAC-AES256
+    const KEY:[u8;32]=[0x60,0x3d,0xeb,0x10,0x15,0xca,0x71,0xbe,0x2b,0x73,0xae,0xf0,0x85,0x7d,0x77,0x81,0x1f,0x35,0x2c,0x07,0x3b,0x61,0x08,0xd7,0x2d,0x98,0x10,0xa3,0x09,0x14,0xdf,0xf4];
+    const MESSAGE:[u8;40]=[0x6b,0xc1,0xbe,0xe2,0x2e,0x40,0x9f,0x96,0xe9,0x3d,0x7e,0x11,0x73,0x93,0x17,0x2a,0xae,0x2d,0x8a,0x57,0x1e,0x03,0xac,0x9c,0x9e,0xb7,0x6f,0xac,0x45,0xaf,0x8e,0x51,0x30,0xc8,0x1c,0x46,0xa3,0x5c,0xe4,0x11];
+    #[test]
+    fn test_cmac_empty() {
+       let expected:[u8;16]=[0x02,0x89,0x62,0xf6,0x1b,0x7b,0xf8,0x9e,0xfc,0x6b,0x55,0x1f,0x46,0x67,0xd9,0x83];
+       assert_eq!(cmac(KEY,&[]),expected);
+    }
+    #[test]
+    fn test_cmac_one_block() {
+       let expected:[u8;16]=[0x28,0xa7,0x02,0x3f,0x45,0x2e,0x8f,0x82,0xbd,0x4b,0xf2,0x8d,0x8c,0x37,0xc3,0x5c];
+       assert_eq!(cmac(KEY,&MESSAGE[..16]),expected);
+    }
+    #[test]
+    fn test_cmac_partial_block() {
+       let expected:[u8;16]=[0xaa,0xf3,0xd8,0xf1,0xde,0x56,0x40,0xc2,0x32,0xf5,0xb1,0x69,0xb9,0xc9,0x11,0xe6];
+       assert_eq!(cmac(KEY,&MESSAGE),expected);
+    }
+}
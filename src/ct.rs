@@ -0,0 +1,214 @@
+/*
+Constant-time AES without secret-indexed table lookups
+The S-box is evaluated bitsliced, up to 16 bytes at once as 8 bit planes,
+by inversion in GF(2^8) (x^254) followed by the affine transform, and
+MixColumns only uses shifts and masks
+*/
+use crate::{inv_shift_rows, rot_word, shift_rows};
+use crate::gf::RCON;
+//
+type Planes = [u16; 8];
+// plane i holds bit i of every byte
+fn to_planes(bytes: &[u8]) -> Planes {
+  let mut planes:Planes = [0; 8];
+  for (j, byte) in bytes.iter().enumerate() {
+    for (i, plane) in planes.iter_mut().enumerate() {
+      *plane |= (((byte >> i) & 1) as u16) << j;
+    }
+  }
+  planes
+}
+//
+fn from_planes(planes: Planes, bytes: &mut [u8]) {
+  for (j, byte) in bytes.iter_mut().enumerate() {
+    *byte = 0;
+    for (i, plane) in planes.iter().enumerate() {
+      *byte |= (((plane >> j) & 1) as u8) << i;
+    }
+  }
+}
+// Product modulo x^8 + x^4 + x^3 + x + 1 on bit planes
+fn mul(a: Planes, b: Planes) -> Planes {
+  let mut p:[u16; 15] = [0; 15];
+  for i in 0..8 {
+    for j in 0..8 {
+      p[i+j] ^= a[i] & b[j];
+    }
+  }
+  for k in (8..15).rev() {
+    p[k-4] ^= p[k];
+    p[k-5] ^= p[k];
+    p[k-7] ^= p[k];
+    p[k-8] ^= p[k];
+  }
+  let mut result:Planes = [0; 8];
+  result.copy_from_slice(&p[..8]);
+  result
+}
+// x^254, 0 maps to 0
+fn inverse(x: Planes) -> Planes {
+  let x2 = mul(x,x);
+  let x3 = mul(x2,x);
+  let x6 = mul(x3,x3);
+  let x12 = mul(x6,x6);
+  let x15 = mul(x12,x3);
+  let mut x240 = x15;
+  for _ in 0..4 {
+    x240 = mul(x240,x240);
+  }
+  mul(mul(x240,x12),x2)
+}
+// out_i = b_i ^ b_i+4 ^ b_i+5 ^ b_i+6 ^ b_i+7 ^ c_i
+fn affine(b: Planes, c: u8) -> Planes {
+  let mut result:Planes = [0; 8];
+  for (i, out) in result.iter_mut().enumerate() {
+    let constant = 0u16.wrapping_sub(((c >> i) & 1) as u16);
+    *out = b[i] ^ b[(i+4)%8] ^ b[(i+5)%8] ^ b[(i+6)%8] ^ b[(i+7)%8] ^ constant;
+  }
+  result
+}
+// out_i = b_i+2 ^ b_i+5 ^ b_i+7 ^ c_i
+fn inv_affine(b: Planes, c: u8) -> Planes {
+  let mut result:Planes = [0; 8];
+  for (i, out) in result.iter_mut().enumerate() {
+    let constant = 0u16.wrapping_sub(((c >> i) & 1) as u16);
+    *out = b[(i+2)%8] ^ b[(i+5)%8] ^ b[(i+7)%8] ^ constant;
+  }
+  result
+}
+// S-box on up to 16 bytes in place
+pub fn sub_bytes(bytes: &mut [u8]) {
+  from_planes(affine(inverse(to_planes(bytes)),0x63),bytes);
+}
+//
+pub fn inv_sub_bytes(bytes: &mut [u8]) {
+  from_planes(inverse(inv_affine(to_planes(bytes),0x05)),bytes);
+}
+//
+fn xtime(a: u8) -> u8 {
+  (a << 1) ^ (0u8.wrapping_sub(a >> 7) & 0x1b)
+}
+//
+fn mix_columns(s: [[u8;4];4]) -> [[u8;4];4] {
+  let mut result:[[u8;4];4] = [[0;4];4];
+  for (column, out) in s.iter().zip(result.iter_mut()) {
+    let all = column[0]^column[1]^column[2]^column[3];
+    for r in 0..4 {
+      // 2a ^ 3b ^ c ^ d = a ^ xtime(a ^ b) ^ (a ^ b ^ c ^ d)
+      out[r] = column[r] ^ xtime(column[r]^column[(r+1)%4]) ^ all;
+    }
+  }
+  result
+}
+//
+fn inv_mix_columns(s: [[u8;4];4]) -> [[u8;4];4] {
+  let mut result:[[u8;4];4] = [[0;4];4];
+  for (column, out) in s.iter().zip(result.iter_mut()) {
+    // multiply by 4x^2 + 5 first, then MixColumns (FIPS-197 4.3 factorisation)
+    let u = xtime(xtime(column[0]^column[2]));
+    let v = xtime(xtime(column[1]^column[3]));
+    let pre = [column[0]^u, column[1]^v, column[2]^u, column[3]^v];
+    *out = mix_columns([pre,[0;4],[0;4],[0;4]])[0];
+  }
+  result
+}
+//
+fn add_round_key(state: &mut [[u8;4];4], key: &[[u8;4];4]) {
+  for (column, key_column) in state.iter_mut().zip(key.iter()) {
+    for (byte, k) in column.iter_mut().zip(key_column.iter()) {
+      *byte ^= k;
+    }
+  }
+}
+// AES key schedule with the bitsliced S-box, dec gives the equivalent
+// inverse cipher schedule like expand_key
+pub fn expand_key(key: &[u8], dec: bool) -> [[[u8; 4] ;4]; 15] {
+  let nk = key.len()/4;
+  let nr = nk+6;
+  let mut w:[[u8; 4]; 60] = [[0; 4]; 60];
+  for i in 0..nk {
+    w[i] = [key[4*i], key[4*i+1], key[4*i+2], key[4*i+3]];
+  }
+  for i in nk..4*(nr+1) {
+    let mut tmp = w[i-1];
+    if i%nk == 0 {
+      tmp = rot_word(tmp);
+      sub_bytes(&mut tmp);
+      tmp[0] ^= RCON[i/nk];
+    } else if nk > 6 && i%nk == 4 {
+      sub_bytes(&mut tmp);
+    }
+    for x in 0..4 {
+      w[i][x] = w[i-nk][x] ^ tmp[x];
+    }
+  }
+  let mut keys:[[[u8; 4]; 4]; 15] = [[[0; 4]; 4]; 15];
+  for (x, round_key) in keys.iter_mut().enumerate().take(nr+1) {
+    round_key.copy_from_slice(&w[4*x..4*x+4]);
+    if dec && x != 0 && x != nr {
+      *round_key = inv_mix_columns(*round_key);
+    }
+  }
+  keys
+}
+//
+pub fn encrypt_block(block: [u8;16], keys: &[[[u8; 4] ;4]; 15], nr: usize) -> [u8;16] {
+  let mut state:[[u8;4];4] = [[0;4];4];
+  state.as_flattened_mut().copy_from_slice(&block);
+  add_round_key(&mut state,&keys[0]);
+  for (i, key) in keys.iter().enumerate().take(nr+1).skip(1) {
+    sub_bytes(state.as_flattened_mut());
+    state = shift_rows(state);
+    if i != nr {
+      state = mix_columns(state);
+    }
+    add_round_key(&mut state,key);
+  }
+  state.as_flattened().try_into().unwrap()
+}
+// Equivalent inverse cipher with the dec schedule
+pub fn decrypt_block(block: [u8;16], keys: &[[[u8; 4] ;4]; 15], nr: usize) -> [u8;16] {
+  let mut state:[[u8;4];4] = [[0;4];4];
+  state.as_flattened_mut().copy_from_slice(&block);
+  add_round_key(&mut state,&keys[nr]);
+  for i in (0..nr).rev() {
+    inv_sub_bytes(state.as_flattened_mut());
+    state = inv_shift_rows(state);
+    if i != 0 {
+      state = inv_mix_columns(state);
+    }
+    add_round_key(&mut state,&keys[i]);
+  }
+  state.as_flattened().try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gf::{INV_SBOX, SBOX};
+    use crate::{expand_key as table_expand_key, AesKey};
+    #[test]
+    fn test_bitsliced_sbox() {
+       for start in (0..256).step_by(16) {
+         let mut bytes:[u8;16] = core::array::from_fn(|j| (start+j) as u8);
+         sub_bytes(&mut bytes);
+         assert_eq!(bytes,SBOX[start..start+16]);
+         let mut bytes:[u8;16] = core::array::from_fn(|j| (start+j) as u8);
+         inv_sub_bytes(&mut bytes);
+         assert_eq!(bytes,INV_SBOX[start..start+16]);
+       }
+    }
+    #[test]
+    fn test_ct_matches_table() {
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       for len in [16,24,32] {
+         let key:Vec<u8> = (0..len as u8).collect();
+         assert_eq!(expand_key(&key,false),table_expand_key::<4>(&key,false));
+         assert_eq!(expand_key(&key,true),table_expand_key::<4>(&key,true));
+         let aes = AesKey::new(&key).unwrap();
+         let cipher = encrypt_block(plain,&aes.enc,aes.rounds);
+         assert_eq!(cipher,aes.encrypt_block(plain));
+         assert_eq!(decrypt_block(cipher,&aes.dec,aes.rounds),plain);
+       }
+    }
+}
@@ -0,0 +1,137 @@
+/*
+Counter mode
+NIST SP 800-38A, the whole 128-bit counter block is incremented as a big
+endian integer, the keystream goes through the bulk path of the key
+*/
+use crate::AesKey;
+#[cfg(feature = "std")]
+use crate::multi::{xor_keystream_multi, Message};
+#[cfg(feature = "std")]
+use crate::parallel::{for_each_chunk, CHUNK};
+//
+fn increment(counter:&mut [u8;16]){
+  for byte in counter.iter_mut().rev() {
+    *byte = byte.wrapping_add(1);
+    if *byte != 0 {
+      break;
+    }
+  }
+}
+// T(i+n) for the counter block T(i)
+pub fn counter_at(counter:[u8;16], n:u128) -> [u8;16]{
+  u128::from_be_bytes(counter).wrapping_add(n).to_be_bytes()
+}
+// XORs the keystream into data, eight blocks at a time
+pub fn ctr_xor_in_place(key:&AesKey, counter:[u8;16], data:&mut [u8]){
+  let mut t = counter;
+  for chunk in data.chunks_mut(8*16) {
+    let mut keystream:[[u8;16];8] = [[0;16];8];
+    let n = chunk.len().div_ceil(16);
+    for block in keystream[..n].iter_mut() {
+      *block = t;
+      increment(&mut t);
+    }
+    key.encrypt_blocks(&mut keystream[..n]);
+    for (byte, k) in chunk.iter_mut().zip(keystream.as_flattened()) {
+      *byte ^= k;
+    }
+  }
+}
+// input XORed with the keystream into the start of output, never allocates
+pub fn ctr_xor_into(key:&AesKey, counter:[u8;16], input:&[u8], output:&mut [u8]) -> Result<(), &'static str>{
+  let output = output.get_mut(..input.len()).ok_or("output buffer is too small")?;
+  output.copy_from_slice(input);
+  ctr_xor_in_place(key,counter,output);
+  Ok(())
+}
+// C(i) = P(i) ^ CIPH(T(i)), the same function decrypts
+#[cfg(feature = "std")]
+pub fn ctr_xor(key:&AesKey, counter:[u8;16], input:&[u8]) -> Vec<u8>{
+  let mut result = input.to_vec();
+  ctr_xor_in_place(key,counter,&mut result);
+  result
+}
+// Same output as ctr_xor, every chunk starts at its own counter block
+#[cfg(feature = "std")]
+pub fn ctr_xor_parallel(key:&AesKey, counter:[u8;16], input:&[u8], threads:usize) -> Vec<u8>{
+  let mut result = input.to_vec();
+  for_each_chunk(threads,&mut result,CHUNK,|index, chunk| {
+    ctr_xor_in_place(key,counter_at(counter,(index*CHUNK/16) as u128),chunk)
+  });
+  result
+}
+// Many messages, each with its own key and 16 byte initial counter block
+// as the nonce, interleaved in the cipher lanes, the aad is not used
+#[cfg(feature = "std")]
+pub fn ctr_xor_many(messages:&[Message]) -> Vec<Result<Vec<u8>, &'static str>>{
+  let counters:Vec<Option<[u8;16]>> = messages.iter().map(|m| m.nonce.try_into().ok()).collect();
+  let keys:Vec<&AesKey> = messages.iter().map(|m| m.key).collect();
+  let mut outputs:Vec<Vec<u8>> = messages.iter().zip(counters.iter())
+    .map(|(m, counter)| if counter.is_some() { m.data.to_vec() } else { vec![] }).collect();
+  xor_keystream_multi(&keys,|i, j| counter_at(counters[i].unwrap(),j as u128),&mut outputs);
+  outputs.into_iter().zip(counters.iter())
+    .map(|(output, counter)| counter.map(|_| output).ok_or("the counter block must be 16 bytes"))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unhex;
+    use crate::Backend;
+    #[test]
+    fn test_ctr() {
+       // NIST SP 800-38A F.5.5 CTR-AES256.Encrypt
+       let key = unhex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4");
+       let counter:[u8;16] = unhex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").try_into().unwrap();
+       let plain = unhex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710");
+       let expected = unhex("601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c52b0930daa23de94ce87017ba2d84988ddfc9c58db67aada613c2dd08457941a6");
+       for backend in [Backend::Table, Backend::ConstantTime, Backend::Bitsliced] {
+         let aes = AesKey::with_backend(&key,backend).unwrap();
+         assert_eq!(ctr_xor(&aes,counter,&plain),expected);
+         assert_eq!(ctr_xor(&aes,counter,&expected),plain);
+       }
+    }
+    #[test]
+    fn test_ctr_wraps() {
+       // partial last block and the counter wrapping around 2^128
+       let key = unhex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4");
+       let counter:[u8;16] = [0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xff,0xfe];
+       let plain = unhex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411");
+       let aes = AesKey::with_backend(&key,Backend::Bitsliced).unwrap();
+       assert_eq!(ctr_xor(&aes,counter,&plain),unhex("245c09a03b1a5a942b93561348a021ce9511a376d65988420471696228b2ee9dd5a0eac7379392c7"));
+       assert!(ctr_xor(&aes,counter,&[]).is_empty());
+       assert_eq!(counter_at(counter,3),[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1]);
+       let mut output:[u8;48] = [0;48];
+       ctr_xor_into(&aes,counter,&plain,&mut output).unwrap();
+       assert_eq!(output[..40],ctr_xor(&aes,counter,&plain));
+       assert!(ctr_xor_into(&aes,counter,&plain,&mut output[..39]).is_err());
+    }
+    #[test]
+    fn test_ctr_many() {
+       let keys:Vec<AesKey> = (0..12).map(|i| AesKey::with_backend(&vec![i as u8; 16+8*(i%3)],
+         if i%2 == 0 { Backend::detect() } else { Backend::Bitsliced }).unwrap()).collect();
+       let nonces:Vec<[u8;16]> = (0..12).map(|i| [0xf0+i as u8; 16]).collect();
+       let data:Vec<Vec<u8>> = (0..12).map(|i| (0..i*37).map(|b| b as u8).collect()).collect();
+       let mut messages:Vec<Message> = (0..12).map(|i| Message { key: &keys[i], nonce: &nonces[i], aad: &[], data: &data[i] }).collect();
+       messages[5].nonce = &nonces[5][..12];
+       let results = ctr_xor_many(&messages);
+       for (i, result) in results.iter().enumerate() {
+         if i == 5 {
+           assert!(result.is_err());
+         } else {
+           assert_eq!(*result.as_ref().unwrap(),ctr_xor(&keys[i],nonces[i],&data[i]));
+         }
+       }
+    }
+    #[test]
+    fn test_ctr_parallel() {
+       let aes = AesKey::with_backend(&[7; 16],Backend::detect()).unwrap();
+       let counter:[u8;16] = [0xff; 16];
+       let input:Vec<u8> = (0..2*CHUNK+37).map(|i| (i*13) as u8).collect();
+       let cipher = ctr_xor(&aes,counter,&input);
+       for threads in [1, 2, 4] {
+         assert_eq!(ctr_xor_parallel(&aes,counter,&input,threads),cipher);
+       }
+    }
+}
@@ -0,0 +1,162 @@
+/*
+Electronic codebook mode
+NIST SP 800-38A, with PKCS#7 padding, blocks go through the bulk path
+of the key so the bitsliced backend encrypts four at a time
+*/
+use crate::AesKey;
+#[cfg(feature = "std")]
+use crate::parallel::{for_each_chunk, CHUNK};
+// PKCS#7 in the room after the first len bytes, always adds 1 to 16 bytes
+fn pad(buffer:&mut [u8], len:usize) -> Result<usize, &'static str>{
+  let pad = 16-len%16;
+  if buffer.len() < len+pad {
+    return Err("buffer has no room for the padding");
+  }
+  buffer[len..len+pad].fill(pad as u8);
+  Ok(len+pad)
+}
+// Length without the padding
+fn unpadded_len(result:&[u8]) -> Result<usize, &'static str>{
+  let pad = result[result.len()-1] as usize;
+  if pad == 0 || pad > 16 || result[result.len()-pad..].iter().any(|b| *b as usize != pad) {
+    return Err("invalid padding");
+  }
+  Ok(result.len()-pad)
+}
+//
+fn check_length(input:&[u8]) -> Result<(), &'static str>{
+  if input.is_empty() || !input.len().is_multiple_of(16) {
+    return Err("ciphertext is not a whole number of blocks");
+  }
+  Ok(())
+}
+// Whole blocks in place without padding, never allocates
+pub fn ecb_encrypt_in_place(key:&AesKey, data:&mut [u8]) -> Result<(), &'static str>{
+  if !data.len().is_multiple_of(16) {
+    return Err("data is not a whole number of blocks");
+  }
+  key.encrypt_blocks(data.as_chunks_mut().0);
+  Ok(())
+}
+//
+pub fn ecb_decrypt_in_place(key:&AesKey, data:&mut [u8]) -> Result<(), &'static str>{
+  if !data.len().is_multiple_of(16) {
+    return Err("data is not a whole number of blocks");
+  }
+  key.decrypt_blocks(data.as_chunks_mut().0);
+  Ok(())
+}
+// Whole blocks of input into the start of output
+pub fn ecb_encrypt_into(key:&AesKey, input:&[u8], output:&mut [u8]) -> Result<(), &'static str>{
+  let output = output.get_mut(..input.len()).ok_or("output buffer is too small")?;
+  output.copy_from_slice(input);
+  ecb_encrypt_in_place(key,output)
+}
+//
+pub fn ecb_decrypt_into(key:&AesKey, input:&[u8], output:&mut [u8]) -> Result<(), &'static str>{
+  let output = output.get_mut(..input.len()).ok_or("output buffer is too small")?;
+  output.copy_from_slice(input);
+  ecb_decrypt_in_place(key,output)
+}
+// Pads the first len bytes of buffer in its spare room and encrypts them,
+// returns the ciphertext part of the buffer
+pub fn ecb_encrypt_padded<'a>(key:&AesKey, buffer:&'a mut [u8], len:usize) -> Result<&'a [u8], &'static str>{
+  let padded = pad(buffer,len)?;
+  key.encrypt_blocks(buffer[..padded].as_chunks_mut().0);
+  Ok(&buffer[..padded])
+}
+// Decrypts the whole buffer in place, returns the plaintext part
+pub fn ecb_decrypt_padded<'a>(key:&AesKey, buffer:&'a mut [u8]) -> Result<&'a [u8], &'static str>{
+  check_length(buffer)?;
+  key.decrypt_blocks(buffer.as_chunks_mut().0);
+  let len = unpadded_len(buffer)?;
+  Ok(&buffer[..len])
+}
+// C(i) = CIPH(P(i))
+#[cfg(feature = "std")]
+pub fn ecb_encrypt(key:&AesKey, input:&[u8]) -> Vec<u8>{
+  ecb_encrypt_parallel(key,input,1)
+}
+// P(i) = INVCIPH(C(i)), then removes the padding
+#[cfg(feature = "std")]
+pub fn ecb_decrypt(key:&AesKey, input:&[u8]) -> Result<Vec<u8>, &'static str>{
+  ecb_decrypt_parallel(key,input,1)
+}
+// Same output as ecb_encrypt, chunks on a pool of threads
+#[cfg(feature = "std")]
+pub fn ecb_encrypt_parallel(key:&AesKey, input:&[u8], threads:usize) -> Vec<u8>{
+  let mut result = input.to_vec();
+  result.resize(input.len()/16*16+16,0);
+  let padded = pad(&mut result,input.len()).unwrap();
+  for_each_chunk(threads,&mut result[..padded],CHUNK,|_, chunk| key.encrypt_blocks(chunk.as_chunks_mut().0));
+  result
+}
+//
+#[cfg(feature = "std")]
+pub fn ecb_decrypt_parallel(key:&AesKey, input:&[u8], threads:usize) -> Result<Vec<u8>, &'static str>{
+  check_length(input)?;
+  let mut result = input.to_vec();
+  for_each_chunk(threads,&mut result,CHUNK,|_, chunk| key.decrypt_blocks(chunk.as_chunks_mut().0));
+  result.truncate(unpadded_len(&result)?);
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unhex;
+    use crate::{aes_encrypt, Backend};
+    #[test]
+    fn test_ecb() {
+       // NIST SP 800-38A F.1.5 ECB-AES256.Encrypt
+       let key = unhex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4");
+       let plain = unhex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710");
+       let expected = unhex("f3eed1bdb5d2a03c064b5a7e3db181f8591ccb10d410ed26dc5ba74a31362870b6ed21b99ca6f4f9f153e7b1beafed1d23304b7a39f9f3ff067d8d8f9e24ecc7");
+       for backend in [Backend::Table, Backend::ConstantTime, Backend::Bitsliced] {
+         let aes = AesKey::with_backend(&key,backend).unwrap();
+         let cipher = ecb_encrypt(&aes,&plain);
+         assert_eq!(cipher.len(),80);
+         assert_eq!(cipher[..64],expected);
+         assert_eq!(ecb_decrypt(&aes,&cipher).unwrap(),plain);
+       }
+       assert!(ecb_decrypt(&AesKey::new(&key).unwrap(),&expected[..15]).is_err());
+    }
+    #[test]
+    fn test_ecb_matches_aes_encrypt() {
+       let key:[u8;32] = [0x42; 32];
+       let input:Vec<u8> = (0..=255).collect();
+       let reference = aes_encrypt(input.clone(),key,input.len()-1);
+       let aes = AesKey::with_backend(&key,Backend::Bitsliced).unwrap();
+       assert_eq!(ecb_encrypt(&aes,&input)[..256],reference[..256]);
+    }
+    #[test]
+    fn test_ecb_in_place() {
+       let aes = AesKey::new(&[0x42; 16]).unwrap();
+       let input:Vec<u8> = (0..45).collect();
+       let expected = ecb_encrypt(&aes,&input);
+       let mut buffer:[u8;64] = [0;64];
+       buffer[..45].copy_from_slice(&input);
+       assert_eq!(ecb_encrypt_padded(&aes,&mut buffer,45).unwrap(),expected);
+       assert_eq!(ecb_decrypt_padded(&aes,&mut buffer[..48]).unwrap(),input);
+       assert!(ecb_encrypt_padded(&aes,&mut buffer[..47],45).is_err());
+       let mut output:[u8;48] = [0;48];
+       ecb_encrypt_into(&aes,&expected,&mut output).unwrap();
+       let mut data = output;
+       ecb_decrypt_in_place(&aes,&mut data).unwrap();
+       assert_eq!(data.to_vec(),expected);
+       ecb_decrypt_into(&aes,&output,&mut data).unwrap();
+       assert_eq!(data.to_vec(),expected);
+       assert!(ecb_encrypt_into(&aes,&expected,&mut output[..32]).is_err());
+       assert!(ecb_encrypt_in_place(&aes,&mut data[..40]).is_err());
+    }
+    #[test]
+    fn test_ecb_parallel() {
+       let aes = AesKey::with_backend(&[0x42; 32],Backend::detect()).unwrap();
+       let input:Vec<u8> = (0..3*CHUNK+100).map(|i| (i*31) as u8).collect();
+       let cipher = ecb_encrypt(&aes,&input);
+       for threads in [1, 2, 5] {
+         assert_eq!(ecb_encrypt_parallel(&aes,&input,threads),cipher);
+         assert_eq!(ecb_decrypt_parallel(&aes,&cipher,threads).unwrap(),input);
+       }
+    }
+}
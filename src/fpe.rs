@@ -0,0 +1,325 @@
+/*
+Format-preserving encryption
+NIST SP 800-38G Rev. 1, FF1 and FF3-1 over AES-256
+Numeral strings are slices of digits in [0, radix), most significant first
+*/
+use std::convert::TryInto;
+use crate::{encrypt_block, key_expansion};
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpeMode {
+    Ff1,
+    Ff31,
+}
+//
+impl FpeMode {
+    pub fn from_name(name: &str) -> Option<FpeMode> {
+        match name {
+            "ff1" => Some(FpeMode::Ff1),
+            "ff3-1" => Some(FpeMode::Ff31),
+            _ => None,
+        }
+    }
+}
+// radix^minlen >= 1000000
+fn check_length(radix:u32, n:usize) -> Result<(), &'static str>{
+  if !(2..=65536).contains(&radix) {
+    return Err("radix must be between 2 and 65536");
+  }
+  let mut domain:u128 = 1;
+  for _i in 0..n {
+    domain = domain.saturating_mul(radix as u128);
+  }
+  if n < 2 || domain < 1_000_000 {
+    return Err("numeral string too short for the radix");
+  }
+  Ok(())
+}
+// NUM_radix(X) as a big endian byte string of the given length
+fn num_bytes(x:&[u32], radix:u32, len:usize) -> Vec<u8>{
+  let mut result:Vec<u8> = vec![0;len];
+  for digit in x {
+    let mut carry = *digit as u64;
+    for byte in result.iter_mut().rev() {
+      let value = (*byte as u64)*(radix as u64)+carry;
+      *byte = value as u8;
+      carry = value>>8;
+    }
+  }
+  result
+}
+// STR^m_radix(NUM(bytes) mod radix^m)
+fn str_digits(bytes:&[u8], radix:u32, m:usize) -> Vec<u32>{
+  let mut value:Vec<u8> = bytes.to_vec();
+  let mut result:Vec<u32> = vec![0;m];
+  for digit in result.iter_mut().rev() {
+    let mut remainder:u64 = 0;
+    for byte in value.iter_mut() {
+      let current = (remainder<<8)|(*byte as u64);
+      *byte = (current/(radix as u64)) as u8;
+      remainder = current%(radix as u64);
+    }
+    *digit = remainder as u32;
+  }
+  result
+}
+// (a + b) mod radix^m, both numeral strings of length m
+fn add_digits(a:&[u32], b:&[u32], radix:u32) -> Vec<u32>{
+  let mut result:Vec<u32> = vec![0;a.len()];
+  let mut carry = 0;
+  for i in (0..a.len()).rev() {
+    let sum = a[i]+b[i]+carry;
+    result[i] = sum%radix;
+    carry = sum/radix;
+  }
+  result
+}
+// (a - b) mod radix^m, both numeral strings of length m
+fn sub_digits(a:&[u32], b:&[u32], radix:u32) -> Vec<u32>{
+  let mut result:Vec<u32> = vec![0;a.len()];
+  let mut borrow = 0;
+  for i in (0..a.len()).rev() {
+    let b_i = b[i]+borrow;
+    if a[i] >= b_i {
+      result[i] = a[i]-b_i;
+      borrow = 0;
+    } else {
+      result[i] = a[i]+radix-b_i;
+      borrow = 1;
+    }
+  }
+  result
+}
+//
+fn xor_block(a:[u8;16], b:&[u8]) -> [u8;16]{
+  let mut result:[u8;16]=a;
+  for (i, item) in b.iter().enumerate() {
+    result[i]^=item;
+  }
+  result
+}
+// FF1 round function, S = first d bytes of R || CIPH(R ^ [1]) || CIPH(R ^ [2]) ...
+fn ff1_round(keys:[[[u8; 4] ;4]; 15], p:&[u8;16], tweak:&[u8], i:u8, x:&[u32], radix:u32, b:usize) -> Vec<u8>{
+  let d = 4*b.div_ceil(4)+4;
+  let mut q:Vec<u8> = tweak.to_vec();
+  let zeros = (16-(tweak.len()+b+1)%16)%16;
+  q.extend(std::iter::repeat_n(0,zeros));
+  q.push(i);
+  q.extend(num_bytes(x,radix,b));
+  // PRF(P || Q), CBC-MAC with a zero IV
+  let mut r = encrypt_block(*p,keys);
+  for block in q.chunks_exact(16) {
+    r = encrypt_block(xor_block(r,block),keys);
+  }
+  let mut s:Vec<u8> = r.to_vec();
+  let mut j:u128 = 1;
+  while s.len() < d {
+    s.extend(encrypt_block(xor_block(r,&j.to_be_bytes()),keys));
+    j+=1;
+  }
+  s.truncate(d);
+  s
+}
+//
+fn ff1(key:[u8;32], tweak:&[u8], radix:u32, x:&[u32], encrypt:bool) -> Result<Vec<u32>, &'static str>{
+  check_length(radix,x.len())?;
+  if x.iter().any(|digit| *digit >= radix) {
+    return Err("digit out of range for the radix");
+  }
+  let n = x.len();
+  let t:u32 = tweak.len().try_into().map_err(|_| "tweak too long")?;
+  let n32:u32 = n.try_into().map_err(|_| "numeral string too long")?;
+  let u = n/2;
+  let v = n-u;
+  let b = ((v as f64)*(radix as f64).log2()).ceil() as usize;
+  let b = b.div_ceil(8);
+  let mut p:[u8;16]=[1,2,1,0,0,0,10,u as u8,0,0,0,0,0,0,0,0];
+  p[3..6].copy_from_slice(&radix.to_be_bytes()[1..]);
+  p[8..12].copy_from_slice(&n32.to_be_bytes());
+  p[12..16].copy_from_slice(&t.to_be_bytes());
+  let keys = key_expansion(key,false);
+  let mut a:Vec<u32> = x[..u].to_vec();
+  let mut b_half:Vec<u32> = x[u..].to_vec();
+  for round in 0..10 {
+    let i:u8 = if encrypt { round } else { 9-round };
+    let m = if i.is_multiple_of(2) { u } else { v };
+    if encrypt {
+      let y = str_digits(&ff1_round(keys,&p,tweak,i,&b_half,radix,b),radix,m);
+      let c = add_digits(&a,&y,radix);
+      a = b_half;
+      b_half = c;
+    } else {
+      let y = str_digits(&ff1_round(keys,&p,tweak,i,&a,radix,b),radix,m);
+      let c = sub_digits(&b_half,&y,radix);
+      b_half = a;
+      a = c;
+    }
+  }
+  a.extend(b_half);
+  Ok(a)
+}
+//
+pub fn ff1_encrypt(key:[u8;32], tweak:&[u8], radix:u32, x:&[u32]) -> Result<Vec<u32>, &'static str>{
+  ff1(key,tweak,radix,x,true)
+}
+//
+pub fn ff1_decrypt(key:[u8;32], tweak:&[u8], radix:u32, x:&[u32]) -> Result<Vec<u32>, &'static str>{
+  ff1(key,tweak,radix,x,false)
+}
+// FF3 cipher with the 64 bit tweak already split in TL and TR
+fn ff3(key:[u8;32], tl:[u8;4], tr:[u8;4], radix:u32, x:&[u32], encrypt:bool) -> Result<Vec<u32>, &'static str>{
+  check_length(radix,x.len())?;
+  if x.iter().any(|digit| *digit >= radix) {
+    return Err("digit out of range for the radix");
+  }
+  // maxlen = 2 * floor(log_radix(2^96))
+  let mut max_half = 0;
+  let mut domain:u128 = radix as u128;
+  while domain <= 1<<96 {
+    max_half+=1;
+    domain*=radix as u128;
+  }
+  if x.len() > 2*max_half {
+    return Err("numeral string too long for the radix");
+  }
+  let n = x.len();
+  let u = n.div_ceil(2);
+  let v = n-u;
+  let mut reversed_key = key;
+  reversed_key.reverse();
+  let keys = key_expansion(reversed_key,false);
+  let mut a:Vec<u32> = x[..u].to_vec();
+  let mut b:Vec<u32> = x[u..].to_vec();
+  for round in 0..8 {
+    let i:u8 = if encrypt { round } else { 7-round };
+    let (m, w) = if i.is_multiple_of(2) { (u, tr) } else { (v, tl) };
+    let mut src:Vec<u32> = if encrypt { b.clone() } else { a.clone() };
+    src.reverse();
+    let mut p:[u8;16]=[0;16];
+    p[..4].copy_from_slice(&w);
+    p[3]^=i;
+    p[4..].copy_from_slice(&num_bytes(&src,radix,12));
+    p.reverse();
+    let mut s = encrypt_block(p,keys);
+    s.reverse();
+    let y = str_digits(&s,radix,m);
+    if encrypt {
+      let mut rev_a = a.clone();
+      rev_a.reverse();
+      let mut c = add_digits(&rev_a,&y,radix);
+      c.reverse();
+      a = b;
+      b = c;
+    } else {
+      let mut rev_b = b.clone();
+      rev_b.reverse();
+      let mut c = sub_digits(&rev_b,&y,radix);
+      c.reverse();
+      b = a;
+      a = c;
+    }
+  }
+  a.extend(b);
+  Ok(a)
+}
+// TL = T[0..3] || T[3] & 0xf0,  TR = T[4..7] || (T[3] & 0x0f) << 4
+fn ff31_tweak(tweak:&[u8]) -> Result<([u8;4],[u8;4]), &'static str>{
+  if tweak.len() != 7 {
+    return Err("FF3-1 tweak must be 56 bits");
+  }
+  let tl:[u8;4]=[tweak[0],tweak[1],tweak[2],tweak[3]&0xf0];
+  let tr:[u8;4]=[tweak[4],tweak[5],tweak[6],(tweak[3]&0x0f)<<4];
+  Ok((tl,tr))
+}
+//
+pub fn ff31_encrypt(key:[u8;32], tweak:&[u8], radix:u32, x:&[u32]) -> Result<Vec<u32>, &'static str>{
+  let (tl,tr) = ff31_tweak(tweak)?;
+  ff3(key,tl,tr,radix,x,true)
+}
+//
+pub fn ff31_decrypt(key:[u8;32], tweak:&[u8], radix:u32, x:&[u32]) -> Result<Vec<u32>, &'static str>{
+  let (tl,tr) = ff31_tweak(tweak)?;
+  ff3(key,tl,tr,radix,x,false)
+}
+// Encrypts the characters of text found in the alphabet, any other
+// character (separators like '-' or ' ') is kept in place
+pub fn fpe_text(mode:FpeMode, key:[u8;32], tweak:&[u8], alphabet:&str, text:&str, encrypt:bool) -> Result<String, &'static str>{
+  let symbols:Vec<char> = alphabet.chars().collect();
+  if symbols.len() < 2 {
+    return Err("alphabet needs at least 2 characters");
+  }
+  if symbols.iter().enumerate().any(|(i, c)| symbols[..i].contains(c)) {
+    return Err("alphabet has a repeated character");
+  }
+  let radix:u32 = symbols.len().try_into().map_err(|_| "alphabet too large")?;
+  let digits:Vec<u32> = text.chars()
+    .filter_map(|c| symbols.iter().position(|s| *s==c))
+    .map(|index| index as u32)
+    .collect();
+  let result = match (mode, encrypt) {
+    (FpeMode::Ff1, true) => ff1_encrypt(key,tweak,radix,&digits)?,
+    (FpeMode::Ff1, false) => ff1_decrypt(key,tweak,radix,&digits)?,
+    (FpeMode::Ff31, true) => ff31_encrypt(key,tweak,radix,&digits)?,
+    (FpeMode::Ff31, false) => ff31_decrypt(key,tweak,radix,&digits)?,
+  };
+  let mut next = result.into_iter();
+  Ok(text.chars()
+    .map(|c| if symbols.contains(&c) { symbols[next.next().unwrap() as usize] } else { c })
+    .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const DIGITS:&str = "0123456789";
+    const BASE36:&str = "0123456789abcdefghijklmnopqrstuvwxyz";
+    #[test]
+    fn test_ff1() {
+       // NIST SP 800-38G FF1 samples 7, 8 and 9 (AES-256)
+       let key:[u8;32]=[0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c,0xef,0x43,0x59,0xd8,0xd5,0x80,0xaa,0x4f,0x7f,0x03,0x6d,0x6f,0x04,0xfc,0x6a,0x94];
+       assert_eq!(fpe_text(FpeMode::Ff1,key,&[],DIGITS,"0123456789",true).unwrap(),"6657667009");
+       let tweak:[u8;10]=[0x39,0x38,0x37,0x36,0x35,0x34,0x33,0x32,0x31,0x30];
+       assert_eq!(fpe_text(FpeMode::Ff1,key,&tweak,DIGITS,"0123456789",true).unwrap(),"1001623463");
+       let tweak:[u8;11]=[0x37,0x37,0x37,0x37,0x70,0x71,0x72,0x73,0x37,0x37,0x37];
+       let cipher = fpe_text(FpeMode::Ff1,key,&tweak,BASE36,"0123456789abcdefghi",true).unwrap();
+       assert_eq!(cipher,"xs8a0azh2avyalyzuwd");
+       assert_eq!(fpe_text(FpeMode::Ff1,key,&tweak,BASE36,&cipher,false).unwrap(),"0123456789abcdefghi");
+    }
+    #[test]
+    fn test_ff3() {
+       // NIST SP 800-38G FF3 samples 11 and 12 (AES-256), FF3-1 only changes the tweak split
+       let key:[u8;32]=[0xef,0x43,0x59,0xd8,0xd5,0x80,0xaa,0x4f,0x7f,0x03,0x6d,0x6f,0x04,0xfc,0x6a,0x94,0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c];
+       let x:Vec<u32> = "890121234567890000".chars().map(|c| c.to_digit(10).unwrap()).collect();
+       let cipher = ff3(key,[0xd8,0xe7,0x92,0x0a],[0xfa,0x33,0x0a,0x73],10,&x,true).unwrap();
+       let expected:Vec<u32> = "922011205562777495".chars().map(|c| c.to_digit(10).unwrap()).collect();
+       assert_eq!(cipher,expected);
+       let cipher = ff3(key,[0x9a,0x76,0x8a,0x92],[0xf6,0x0e,0x12,0xd8],10,&x,true).unwrap();
+       let expected:Vec<u32> = "504149865578056140".chars().map(|c| c.to_digit(10).unwrap()).collect();
+       assert_eq!(cipher,expected);
+       assert_eq!(ff3(key,[0x9a,0x76,0x8a,0x92],[0xf6,0x0e,0x12,0xd8],10,&cipher,false).unwrap(),x);
+    }
+    #[test]
+    fn test_ff31() {
+       let key:[u8;32]=[0xef,0x43,0x59,0xd8,0xd5,0x80,0xaa,0x4f,0x7f,0x03,0x6d,0x6f,0x04,0xfc,0x6a,0x94,0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c];
+       let tweak:[u8;7]=[0xd8,0xe7,0x92,0x0a,0xfa,0x33,0x0a];
+       let cipher = fpe_text(FpeMode::Ff31,key,&tweak,DIGITS,"890121234567890000",true).unwrap();
+       assert_eq!(cipher,"739867966748611431");
+       assert_eq!(fpe_text(FpeMode::Ff31,key,&tweak,DIGITS,&cipher,false).unwrap(),"890121234567890000");
+       let tweak:[u8;7]=[0x9a,0x76,0x8a,0x92,0xf6,0x0e,0x12];
+       assert_eq!(fpe_text(FpeMode::Ff31,key,&tweak,BASE36,"0123456789abcdefghi",true).unwrap(),"av0stw2faj69bjw20i8");
+       assert!(ff31_encrypt(key,&[0;8],10,&[1,2,3,4,5,6,7,8]).is_err());
+    }
+    #[test]
+    fn test_fpe_text_format() {
+       let key:[u8;32]=[7; 32];
+       let card = "4111-1111-1111-1111";
+       let cipher = fpe_text(FpeMode::Ff1,key,&[],DIGITS,card,true).unwrap();
+       assert_eq!(cipher.len(),card.len());
+       assert_eq!(cipher.chars().filter(|c| *c=='-').count(),3);
+       assert_eq!(&cipher[4..5],"-");
+       assert_eq!(fpe_text(FpeMode::Ff1,key,&[],DIGITS,&cipher,false).unwrap(),card);
+       assert!(fpe_text(FpeMode::Ff1,key,&[],DIGITS,"12345",true).is_err());
+       assert_eq!(fpe_text(FpeMode::Ff1,key,&[],"00123456789","1234-5678-9012",true),Err("alphabet has a repeated character"));
+       assert!(fpe_text(FpeMode::Ff1,key,&[],"0","000000",true).is_err());
+    }
+}
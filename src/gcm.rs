@@ -0,0 +1,265 @@
+/*
+Galois/Counter mode
+NIST SP 800-38D, GCTR keystream through the bulk path of the key and the
+tag from ghash, which uses PCLMULQDQ when the CPU has it
+*/
+use crate::AesKey;
+use crate::ghash::{Ghash, UniversalHash};
+#[cfg(feature = "std")]
+use crate::multi::{xor_keystream_multi, Message};
+#[cfg(feature = "std")]
+use crate::parallel::{for_each_chunk, CHUNK};
+// NIST SP 800-38D limit of 2^39-256 bits, 2^32-2 blocks after J0 so the
+// 32-bit counter never wraps back to J0, which masks the tag
+const MAX_LEN:u64 = (1<<36)-32;
+// SP 800-38D also needs an IV of at least 1 bit
+fn check_input(nonce:&[u8], len:usize) -> Result<(), &'static str>{
+  if nonce.is_empty() {
+    return Err("GCM nonce is empty");
+  }
+  if len as u64 > MAX_LEN {
+    return Err("GCM input is longer than 2^39-256 bits");
+  }
+  Ok(())
+}
+// Only the rightmost 32 bits are incremented
+fn add32(counter:[u8;16], n:u32) -> [u8;16]{
+  let mut result = counter;
+  let low = u32::from_be_bytes(counter[12..].try_into().unwrap()).wrapping_add(n);
+  result[12..].copy_from_slice(&low.to_be_bytes());
+  result
+}
+// XORs the keystream into data, eight blocks at a time
+fn gctr_in_place(key:&AesKey, icb:[u8;16], data:&mut [u8]){
+  let mut cb = icb;
+  for chunk in data.chunks_mut(8*16) {
+    let mut keystream:[[u8;16];8] = [[0;16];8];
+    let n = chunk.len().div_ceil(16);
+    for block in keystream[..n].iter_mut() {
+      *block = cb;
+      cb = add32(cb,1);
+    }
+    key.encrypt_blocks(&mut keystream[..n]);
+    for (byte, k) in chunk.iter_mut().zip(keystream.as_flattened()) {
+      *byte ^= k;
+    }
+  }
+}
+// GCTR from inc32(J0), on a pool of threads when threads > 1, the input
+// is at most MAX_LEN so the block offset of a chunk fits in 32 bits
+#[cfg(feature = "std")]
+fn gctr(key:&AesKey, j0:[u8;16], input:&[u8], threads:usize) -> Vec<u8>{
+  let mut result = input.to_vec();
+  for_each_chunk(threads,&mut result,CHUNK,|index, chunk| {
+    let offset:u32 = (1+index*CHUNK/16).try_into().expect("input checked against MAX_LEN");
+    gctr_in_place(key,add32(j0,offset),chunk)
+  });
+  result
+}
+// H and the pre-counter block J0
+fn setup(key:&AesKey, nonce:&[u8]) -> (Ghash, [u8;16]){
+  let h = key.encrypt_block([0;16]);
+  let mut j0:[u8;16] = [0;16];
+  if nonce.len() == 12 {
+    j0[..12].copy_from_slice(nonce);
+    j0[15] = 1;
+  } else {
+    let mut ghash = Ghash::new(h);
+    ghash.update_padded(nonce);
+    let mut lengths:[u8;16] = [0;16];
+    lengths[8..].copy_from_slice(&(8*nonce.len() as u64).to_be_bytes());
+    ghash.update(&[lengths]);
+    j0 = ghash.finalize();
+  }
+  (Ghash::new(h), j0)
+}
+//
+fn tag(key:&AesKey, mut ghash:Ghash, j0:[u8;16], aad:&[u8], ciphertext:&[u8]) -> [u8;16]{
+  ghash.update_padded(aad);
+  ghash.update_padded(ciphertext);
+  let mut lengths:[u8;16] = [0;16];
+  lengths[..8].copy_from_slice(&(8*aad.len() as u64).to_be_bytes());
+  lengths[8..].copy_from_slice(&(8*ciphertext.len() as u64).to_be_bytes());
+  ghash.update(&[lengths]);
+  let s = ghash.finalize();
+  let e = key.encrypt_block(j0);
+  core::array::from_fn(|i| s[i]^e[i])
+}
+// Encrypts data in place and returns the tag, never allocates
+pub fn gcm_encrypt_in_place(key:&AesKey, nonce:&[u8], aad:&[u8], data:&mut [u8]) -> Result<[u8;16], &'static str>{
+  check_input(nonce,data.len())?;
+  let (ghash, j0) = setup(key,nonce);
+  gctr_in_place(key,add32(j0,1),data);
+  Ok(tag(key,ghash,j0,aad,data))
+}
+// Leaves data untouched when the tag does not match
+pub fn gcm_decrypt_in_place(key:&AesKey, nonce:&[u8], aad:&[u8], data:&mut [u8], expected:&[u8;16]) -> Result<(), &'static str>{
+  check_input(nonce,data.len())?;
+  let (ghash, j0) = setup(key,nonce);
+  verify(&tag(key,ghash,j0,aad,data),expected)?;
+  gctr_in_place(key,add32(j0,1),data);
+  Ok(())
+}
+// Constant-time tag comparison
+fn verify(computed:&[u8;16], expected:&[u8;16]) -> Result<(), &'static str>{
+  if computed.iter().zip(expected.iter()).fold(0, |d, (a, b)| d | (a^b)) != 0 {
+    return Err("authentication failed");
+  }
+  Ok(())
+}
+// Returns the ciphertext and the 128-bit tag
+#[cfg(feature = "std")]
+pub fn gcm_encrypt(key:&AesKey, nonce:&[u8], aad:&[u8], plaintext:&[u8]) -> Result<(Vec<u8>, [u8;16]), &'static str>{
+  gcm_encrypt_parallel(key,nonce,aad,plaintext,1)
+}
+// The tag is compared in constant time before anything is decrypted
+#[cfg(feature = "std")]
+pub fn gcm_decrypt(key:&AesKey, nonce:&[u8], aad:&[u8], ciphertext:&[u8], expected:&[u8;16]) -> Result<Vec<u8>, &'static str>{
+  gcm_decrypt_parallel(key,nonce,aad,ciphertext,expected,1)
+}
+// Same output as gcm_encrypt, the counter mode chunks run on a pool of
+// threads and GHASH goes over the whole ciphertext afterwards
+#[cfg(feature = "std")]
+pub fn gcm_encrypt_parallel(key:&AesKey, nonce:&[u8], aad:&[u8], plaintext:&[u8], threads:usize) -> Result<(Vec<u8>, [u8;16]), &'static str>{
+  check_input(nonce,plaintext.len())?;
+  let (ghash, j0) = setup(key,nonce);
+  let ciphertext = gctr(key,j0,plaintext,threads);
+  let tag = tag(key,ghash,j0,aad,&ciphertext);
+  Ok((ciphertext, tag))
+}
+//
+#[cfg(feature = "std")]
+pub fn gcm_decrypt_parallel(key:&AesKey, nonce:&[u8], aad:&[u8], ciphertext:&[u8], expected:&[u8;16], threads:usize) -> Result<Vec<u8>, &'static str>{
+  check_input(nonce,ciphertext.len())?;
+  let (ghash, j0) = setup(key,nonce);
+  verify(&tag(key,ghash,j0,aad,ciphertext),expected)?;
+  Ok(gctr(key,j0,ciphertext,threads))
+}
+// Ciphertext and tag of one message of gcm_encrypt_many
+#[cfg(feature = "std")]
+pub type Sealed = Result<(Vec<u8>, [u8;16]), &'static str>;
+// Many messages, each with its own key, nonce and aad, the counter mode
+// blocks of all of them are interleaved in the cipher lanes
+#[cfg(feature = "std")]
+pub fn gcm_encrypt_many(messages:&[Message]) -> Vec<Sealed>{
+  let checked:Vec<Result<(), &'static str>> = messages.iter().map(|m| check_input(m.nonce,m.data.len())).collect();
+  let setups:Vec<(Ghash, [u8;16])> = messages.iter().map(|m| setup(m.key,m.nonce)).collect();
+  let keys:Vec<&AesKey> = messages.iter().map(|m| m.key).collect();
+  let mut outputs:Vec<Vec<u8>> = messages.iter().zip(checked.iter())
+    .map(|(m, ok)| if ok.is_ok() { m.data.to_vec() } else { vec![] }).collect();
+  xor_keystream_multi(&keys,|i, j| add32(setups[i].1,1+j as u32),&mut outputs);
+  outputs.into_iter().zip(setups).zip(messages.iter()).zip(checked)
+    .map(|(((ciphertext, (ghash, j0)), m), ok)| {
+      ok.map(|_| {
+        let tag = tag(m.key,ghash,j0,m.aad,&ciphertext);
+        (ciphertext, tag)
+      })
+    })
+    .collect()
+}
+// tags[i] authenticates message i, only the messages that verify are decrypted
+#[cfg(feature = "std")]
+pub fn gcm_decrypt_many(messages:&[Message], tags:&[[u8;16]]) -> Vec<Result<Vec<u8>, &'static str>>{
+  assert_eq!(messages.len(),tags.len(),"one tag per message");
+  let setups:Vec<(Ghash, [u8;16])> = messages.iter().map(|m| setup(m.key,m.nonce)).collect();
+  let verified:Vec<Result<(), &'static str>> = messages.iter().zip(setups.iter()).zip(tags.iter())
+    .map(|((m, (ghash, j0)), expected)| {
+      check_input(m.nonce,m.data.len()).and_then(|_| verify(&tag(m.key,ghash.clone(),*j0,m.aad,m.data),expected))
+    })
+    .collect();
+  let keys:Vec<&AesKey> = messages.iter().map(|m| m.key).collect();
+  let mut outputs:Vec<Vec<u8>> = messages.iter().zip(verified.iter())
+    .map(|(m, ok)| if ok.is_ok() { m.data.to_vec() } else { vec![] }).collect();
+  xor_keystream_multi(&keys,|i, j| add32(setups[i].1,1+j as u32),&mut outputs);
+  outputs.into_iter().zip(verified).map(|(output, ok)| ok.map(|_| output)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unhex;
+    use crate::Backend;
+    #[test]
+    fn test_gcm() {
+       let key:Vec<u8> = (0..32).collect();
+       let plain:Vec<u8> = (0..70).map(|i| ((i*11+3)%256) as u8).collect();
+       let aad:Vec<u8> = (100..120).collect();
+       let cases = [
+         ("cafebabefacedbaddecaf888", "89adb90285400a4b1d6d2ca1fc8f1497be9e098500f39f7445c9255d89cb28a7ce3eb8c24e4eecd29d9df9b4d571dd6d4a4a80659cc0ddb048e4f0b029461afaa24c8796a0beb154ec7a1a9ca803ec670c05214b2081"),
+         // nonce other than 96 bits goes through GHASH
+         ("cafebabefacedbad", "7d78b8b7cbb5496702e34bd09d8ec35526bf9514de4372df2ac8af36b0cdac2e6ba79a7d3cbd390a5eb12650eca4a7180752372525ec95da7253c93e0190287cd0c13a75b5e973022a1ce01289ef02e1c5d019ad2bd1"),
+       ];
+       for backend in [Backend::Table, Backend::AesNi, Backend::TTable, Backend::Bitsliced] {
+         let aes = AesKey::with_backend(&key,backend).unwrap();
+         for (nonce, expected) in cases {
+           let nonce = unhex(nonce);
+           let expected = unhex(expected);
+           let (cipher, tag) = gcm_encrypt(&aes,&nonce,&aad,&plain).unwrap();
+           assert_eq!(cipher,expected[..70]);
+           assert_eq!(tag,expected[70..]);
+           assert_eq!(gcm_decrypt(&aes,&nonce,&aad,&cipher,&tag).unwrap(),plain);
+           let mut forged = tag;
+           forged[0] ^= 1;
+           assert!(gcm_decrypt(&aes,&nonce,&aad,&cipher,&forged).is_err());
+         }
+       }
+       assert!(check_input(&[0;12],MAX_LEN as usize).is_ok());
+       assert!(check_input(&[0;12],MAX_LEN as usize+1).is_err());
+       assert!(gcm_encrypt(&AesKey::new(&[0;16]).unwrap(),&[],&aad,&plain).is_err());
+       assert!(gcm_decrypt(&AesKey::new(&[0;16]).unwrap(),&[],&aad,&[],&[0;16]).is_err());
+       // McGrew and Viega test case 1
+       let (cipher, tag) = gcm_encrypt(&AesKey::new(&[0;16]).unwrap(),&[0;12],&[],&[]).unwrap();
+       assert!(cipher.is_empty());
+       assert_eq!(tag.to_vec(),unhex("58e2fccefa7e3061367f1d57a4e7455a"));
+    }
+    #[test]
+    fn test_gcm_in_place() {
+       let aes = AesKey::new(&[3; 16]).unwrap();
+       let nonce = [5; 12];
+       let mut data:[u8;37] = core::array::from_fn(|i| i as u8);
+       let (expected, expected_tag) = gcm_encrypt(&aes,&nonce,b"aad",&data).unwrap();
+       assert!(gcm_encrypt_in_place(&aes,&[],b"aad",&mut data).is_err());
+       let tag = gcm_encrypt_in_place(&aes,&nonce,b"aad",&mut data).unwrap();
+       assert_eq!((data.to_vec(), tag),(expected, expected_tag));
+       let mut forged = tag;
+       forged[15] ^= 0x80;
+       assert!(gcm_decrypt_in_place(&aes,&nonce,b"aad",&mut data,&forged).is_err());
+       gcm_decrypt_in_place(&aes,&nonce,b"aad",&mut data,&tag).unwrap();
+       assert_eq!(data,core::array::from_fn(|i| i as u8));
+    }
+    #[test]
+    fn test_gcm_many() {
+       let keys:Vec<AesKey> = (0..10).map(|i| AesKey::with_backend(&vec![i as u8; 16+8*(i%3)],Backend::detect()).unwrap()).collect();
+       let nonces:Vec<Vec<u8>> = (0..10).map(|i| vec![i as u8; if i == 3 { 8 } else { 12 }]).collect();
+       let data:Vec<Vec<u8>> = (0..10).map(|i| (0..i*45).map(|b| (b*i) as u8).collect()).collect();
+       let messages:Vec<Message> = (0..10).map(|i| Message { key: &keys[i], nonce: &nonces[i], aad: &data[9][..3*i], data: &data[i] }).collect();
+       let sealed:Vec<(Vec<u8>, [u8;16])> = gcm_encrypt_many(&messages).into_iter().map(|result| result.unwrap()).collect();
+       for (m, result) in messages.iter().zip(sealed.iter()) {
+         assert_eq!(*result,gcm_encrypt(m.key,m.nonce,m.aad,m.data).unwrap());
+       }
+       let empty_nonce = [Message { nonce: &[], ..messages[0] }];
+       assert!(gcm_encrypt_many(&empty_nonce)[0].is_err());
+       assert!(gcm_decrypt_many(&empty_nonce,&[sealed[0].1])[0].is_err());
+       let ciphertexts:Vec<Message> = messages.iter().zip(sealed.iter()).map(|(m, (c, _))| Message { data: c, ..*m }).collect();
+       let mut tags:Vec<[u8;16]> = sealed.iter().map(|(_, tag)| *tag).collect();
+       tags[7][0] ^= 1;
+       for (i, result) in gcm_decrypt_many(&ciphertexts,&tags).iter().enumerate() {
+         if i == 7 {
+           assert!(result.is_err());
+         } else {
+           assert_eq!(*result.as_ref().unwrap(),data[i]);
+         }
+       }
+    }
+    #[test]
+    fn test_gcm_parallel() {
+       let aes = AesKey::with_backend(&[9; 32],Backend::detect()).unwrap();
+       let nonce = unhex("fffffffffffffffffffffffffffffffff0");
+       let input:Vec<u8> = (0..2*CHUNK+5).map(|i| (i*3) as u8).collect();
+       let (cipher, tag) = gcm_encrypt(&aes,&nonce,b"header",&input).unwrap();
+       for threads in [2, 3] {
+         assert_eq!(gcm_encrypt_parallel(&aes,&nonce,b"header",&input,threads).unwrap(),(cipher.clone(), tag));
+         assert_eq!(gcm_decrypt_parallel(&aes,&nonce,b"header",&cipher,&tag,threads).unwrap(),input);
+       }
+    }
+}
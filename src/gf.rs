@@ -0,0 +1,186 @@
+/*
+GF(2^8) arithmetic
+Polynomial basis modulo x^8 + x^4 + x^3 + x + 1 (FIPS-197 section 4),
+the S-boxes, multiplication tables and round constants are generated
+from it at compile time
+*/
+// multiplication by x
+pub const fn xtime(a: u8) -> u8 {
+  if a & 0x80 != 0 { (a << 1) ^ 0x1b } else { a << 1 }
+}
+//
+pub const fn mul(a: u8, b: u8) -> u8 {
+  let mut a = a;
+  let mut b = b;
+  let mut result = 0;
+  while b != 0 {
+    if b & 1 != 0 {
+      result ^= a;
+    }
+    a = xtime(a);
+    b >>= 1;
+  }
+  result
+}
+// a^254 = a^-1, and 0 maps to 0
+pub const fn inverse(a: u8) -> u8 {
+  let mut result = 1;
+  let mut square = a;
+  let mut exponent = 254;
+  while exponent != 0 {
+    if exponent & 1 != 0 {
+      result = mul(result,square);
+    }
+    square = mul(square,square);
+    exponent >>= 1;
+  }
+  result
+}
+// b ^ (b <<< 1) ^ (b <<< 2) ^ (b <<< 3) ^ (b <<< 4) ^ c
+pub const fn affine(b: u8, c: u8) -> u8 {
+  b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ c
+}
+// (b <<< 1) ^ (b <<< 3) ^ (b <<< 6) ^ c, the inverse of affine(b, 0x63) for c = 0x05
+pub const fn inv_affine(b: u8, c: u8) -> u8 {
+  b.rotate_left(1) ^ b.rotate_left(3) ^ b.rotate_left(6) ^ c
+}
+//
+const fn sbox_table() -> [u8;256] {
+  let mut table = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    table[x] = affine(inverse(x as u8),0x63);
+    x += 1;
+  }
+  table
+}
+//
+const fn inv_sbox_table() -> [u8;256] {
+  let mut table = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    table[x] = inverse(inv_affine(x as u8,0x05));
+    x += 1;
+  }
+  table
+}
+//
+const fn mul_table(a: u8) -> [u8;256] {
+  let mut table = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    table[x] = mul(a,x as u8);
+    x += 1;
+  }
+  table
+}
+// RCON[0] = x^-1 = 0x8d so that RCON[i] = x^(i-1)
+const fn rcon_table() -> [u8;256] {
+  let mut table = [0;256];
+  let mut r = 0x8d;
+  let mut i = 0;
+  while i < 256 {
+    table[i] = r;
+    r = xtime(r);
+    i += 1;
+  }
+  table
+}
+//
+pub const SBOX: [u8;256] = sbox_table();
+pub const INV_SBOX: [u8;256] = inv_sbox_table();
+pub const MUL2: [u8;256] = mul_table(2);
+pub const MUL3: [u8;256] = mul_table(3);
+pub const MUL9: [u8;256] = mul_table(9);
+pub const MUL11: [u8;256] = mul_table(11);
+pub const MUL13: [u8;256] = mul_table(13);
+pub const MUL14: [u8;256] = mul_table(14);
+pub const RCON: [u8;256] = rcon_table();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // FIPS-197 Figure 7 and Figure 14, and the round constants previously
+    // pasted into key_expansion
+    const FIPS_SBOX:[u8;256] = [
+         0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+         0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+         0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+         0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+         0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+         0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+         0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+         0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+         0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+         0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+         0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+         0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+         0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+         0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+         0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+         0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+    ];
+    const FIPS_INV_SBOX:[u8;256] = [
+         0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3, 0xd7, 0xfb,
+         0x7c, 0xe3, 0x39, 0x82, 0x9b, 0x2f, 0xff, 0x87, 0x34, 0x8e, 0x43, 0x44, 0xc4, 0xde, 0xe9, 0xcb,
+         0x54, 0x7b, 0x94, 0x32, 0xa6, 0xc2, 0x23, 0x3d, 0xee, 0x4c, 0x95, 0x0b, 0x42, 0xfa, 0xc3, 0x4e,
+         0x08, 0x2e, 0xa1, 0x66, 0x28, 0xd9, 0x24, 0xb2, 0x76, 0x5b, 0xa2, 0x49, 0x6d, 0x8b, 0xd1, 0x25,
+         0x72, 0xf8, 0xf6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xd4, 0xa4, 0x5c, 0xcc, 0x5d, 0x65, 0xb6, 0x92,
+         0x6c, 0x70, 0x48, 0x50, 0xfd, 0xed, 0xb9, 0xda, 0x5e, 0x15, 0x46, 0x57, 0xa7, 0x8d, 0x9d, 0x84,
+         0x90, 0xd8, 0xab, 0x00, 0x8c, 0xbc, 0xd3, 0x0a, 0xf7, 0xe4, 0x58, 0x05, 0xb8, 0xb3, 0x45, 0x06,
+         0xd0, 0x2c, 0x1e, 0x8f, 0xca, 0x3f, 0x0f, 0x02, 0xc1, 0xaf, 0xbd, 0x03, 0x01, 0x13, 0x8a, 0x6b,
+         0x3a, 0x91, 0x11, 0x41, 0x4f, 0x67, 0xdc, 0xea, 0x97, 0xf2, 0xcf, 0xce, 0xf0, 0xb4, 0xe6, 0x73,
+         0x96, 0xac, 0x74, 0x22, 0xe7, 0xad, 0x35, 0x85, 0xe2, 0xf9, 0x37, 0xe8, 0x1c, 0x75, 0xdf, 0x6e,
+         0x47, 0xf1, 0x1a, 0x71, 0x1d, 0x29, 0xc5, 0x89, 0x6f, 0xb7, 0x62, 0x0e, 0xaa, 0x18, 0xbe, 0x1b,
+         0xfc, 0x56, 0x3e, 0x4b, 0xc6, 0xd2, 0x79, 0x20, 0x9a, 0xdb, 0xc0, 0xfe, 0x78, 0xcd, 0x5a, 0xf4,
+         0x1f, 0xdd, 0xa8, 0x33, 0x88, 0x07, 0xc7, 0x31, 0xb1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xec, 0x5f,
+         0x60, 0x51, 0x7f, 0xa9, 0x19, 0xb5, 0x4a, 0x0d, 0x2d, 0xe5, 0x7a, 0x9f, 0x93, 0xc9, 0x9c, 0xef,
+         0xa0, 0xe0, 0x3b, 0x4d, 0xae, 0x2a, 0xf5, 0xb0, 0xc8, 0xeb, 0xbb, 0x3c, 0x83, 0x53, 0x99, 0x61,
+         0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
+    ];
+    const FIPS_RCON:[u8;256] = [
+         0x8d, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36, 0x6c, 0xd8, 0xab, 0x4d, 0x9a,
+         0x2f, 0x5e, 0xbc, 0x63, 0xc6, 0x97, 0x35, 0x6a, 0xd4, 0xb3, 0x7d, 0xfa, 0xef, 0xc5, 0x91, 0x39,
+         0x72, 0xe4, 0xd3, 0xbd, 0x61, 0xc2, 0x9f, 0x25, 0x4a, 0x94, 0x33, 0x66, 0xcc, 0x83, 0x1d, 0x3a,
+         0x74, 0xe8, 0xcb, 0x8d, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36, 0x6c, 0xd8,
+         0xab, 0x4d, 0x9a, 0x2f, 0x5e, 0xbc, 0x63, 0xc6, 0x97, 0x35, 0x6a, 0xd4, 0xb3, 0x7d, 0xfa, 0xef,
+         0xc5, 0x91, 0x39, 0x72, 0xe4, 0xd3, 0xbd, 0x61, 0xc2, 0x9f, 0x25, 0x4a, 0x94, 0x33, 0x66, 0xcc,
+         0x83, 0x1d, 0x3a, 0x74, 0xe8, 0xcb, 0x8d, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b,
+         0x36, 0x6c, 0xd8, 0xab, 0x4d, 0x9a, 0x2f, 0x5e, 0xbc, 0x63, 0xc6, 0x97, 0x35, 0x6a, 0xd4, 0xb3,
+         0x7d, 0xfa, 0xef, 0xc5, 0x91, 0x39, 0x72, 0xe4, 0xd3, 0xbd, 0x61, 0xc2, 0x9f, 0x25, 0x4a, 0x94,
+         0x33, 0x66, 0xcc, 0x83, 0x1d, 0x3a, 0x74, 0xe8, 0xcb, 0x8d, 0x01, 0x02, 0x04, 0x08, 0x10, 0x20,
+         0x40, 0x80, 0x1b, 0x36, 0x6c, 0xd8, 0xab, 0x4d, 0x9a, 0x2f, 0x5e, 0xbc, 0x63, 0xc6, 0x97, 0x35,
+         0x6a, 0xd4, 0xb3, 0x7d, 0xfa, 0xef, 0xc5, 0x91, 0x39, 0x72, 0xe4, 0xd3, 0xbd, 0x61, 0xc2, 0x9f,
+         0x25, 0x4a, 0x94, 0x33, 0x66, 0xcc, 0x83, 0x1d, 0x3a, 0x74, 0xe8, 0xcb, 0x8d, 0x01, 0x02, 0x04,
+         0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36, 0x6c, 0xd8, 0xab, 0x4d, 0x9a, 0x2f, 0x5e, 0xbc, 0x63,
+         0xc6, 0x97, 0x35, 0x6a, 0xd4, 0xb3, 0x7d, 0xfa, 0xef, 0xc5, 0x91, 0x39, 0x72, 0xe4, 0xd3, 0xbd,
+         0x61, 0xc2, 0x9f, 0x25, 0x4a, 0x94, 0x33, 0x66, 0xcc, 0x83, 0x1d, 0x3a, 0x74, 0xe8, 0xcb, 0x8d,
+    ];
+    #[test]
+    fn test_tables_match_fips() {
+       assert_eq!(SBOX,FIPS_SBOX);
+       assert_eq!(INV_SBOX,FIPS_INV_SBOX);
+       assert_eq!(RCON,FIPS_RCON);
+       assert_eq!(RCON[1..11],[0x01,0x02,0x04,0x08,0x10,0x20,0x40,0x80,0x1b,0x36]);
+    }
+    #[test]
+    fn test_mul() {
+       // FIPS-197 section 4.2
+       assert_eq!(mul(0x57,0x83),0xc1);
+       assert_eq!(mul(0x57,0x13),0xfe);
+       assert_eq!(xtime(0x57),0xae);
+       assert_eq!(xtime(0x8e),0x07);
+       for x in 0..=255u8 {
+         assert_eq!(MUL3[x as usize],MUL2[x as usize]^x);
+         assert_eq!(MUL9[x as usize],mul(8,x)^x);
+         assert_eq!(MUL11[x as usize],mul(8,x)^MUL3[x as usize]);
+         assert_eq!(MUL13[x as usize],mul(12,x)^x);
+         assert_eq!(MUL14[x as usize],mul(12,x)^MUL2[x as usize]);
+         if x != 0 {
+           assert_eq!(mul(x,inverse(x)),1);
+         }
+         assert_eq!(inv_affine(affine(x,0x63),0x05),x);
+       }
+       assert_eq!(inverse(0),0);
+    }
+}
@@ -0,0 +1,271 @@
+/*
+GHASH and POLYVAL universal hashes
+POLYVAL (RFC 8452) is computed with a carry-less multiply, PCLMULQDQ when
+the x86-64 CPU has it and a constant-time integer multiply with holes
+otherwise, four blocks at a time against H^4..H with a single reduction.
+GHASH (NIST SP 800-38D) is POLYVAL on byte reversed blocks with H*x
+*/
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+// Common interface of GHASH and POLYVAL
+pub trait UniversalHash {
+  fn update(&mut self, blocks: &[[u8;16]]);
+  fn finalize(&self) -> [u8;16];
+  // zero pads a partial final block
+  fn update_padded(&mut self, data: &[u8]) {
+    let (blocks, tail) = data.as_chunks::<16>();
+    self.update(blocks);
+    if !tail.is_empty() {
+      let mut last:[u8;16] = [0;16];
+      last[..tail.len()].copy_from_slice(tail);
+      self.update(&[last]);
+    }
+  }
+}
+//
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+pub fn clmul_available() -> bool {
+  is_x86_feature_detected!("pclmulqdq") && is_x86_feature_detected!("sse2")
+}
+//
+#[cfg(all(target_arch = "x86_64", not(feature = "std")))]
+pub fn clmul_available() -> bool {
+  cfg!(target_feature = "pclmulqdq") && cfg!(target_feature = "sse2")
+}
+//
+#[cfg(not(target_arch = "x86_64"))]
+pub fn clmul_available() -> bool {
+  false
+}
+// Carry-less 32x32 multiply with integer multiplies, every fourth bit is
+// kept so the at most 8 carries of a position never reach the next one
+fn clmul32(x: u32, y: u32) -> u64 {
+  let masks:[u32;4] = [0x1111_1111, 0x2222_2222, 0x4444_4444, 0x8888_8888];
+  let xs = masks.map(|m| (x & m) as u64);
+  let ys = masks.map(|m| (y & m) as u64);
+  let mut result = 0;
+  for i in 0..4 {
+    let mut z = 0;
+    for j in 0..4 {
+      z ^= xs[j].wrapping_mul(ys[(4+i-j)%4]);
+    }
+    result |= z & (0x1111_1111_1111_1111 << i);
+  }
+  result
+}
+//
+fn clmul64(x: u64, y: u64) -> u128 {
+  let (x0, x1) = (x as u32, (x >> 32) as u32);
+  let (y0, y1) = (y as u32, (y >> 32) as u32);
+  let mid = clmul32(x0,y1) ^ clmul32(x1,y0);
+  (clmul32(x0,y0) as u128) ^ ((mid as u128) << 32) ^ ((clmul32(x1,y1) as u128) << 64)
+}
+// Sum of the unreduced 256-bit products as four words, low first
+fn product_soft(pairs: &[(u128, u128)]) -> [u64;4] {
+  let (mut lo, mut mid, mut hi) = (0u128, 0u128, 0u128);
+  for &(a, b) in pairs {
+    let (a0, a1, b0, b1) = (a as u64, (a >> 64) as u64, b as u64, (b >> 64) as u64);
+    lo ^= clmul64(a0,b0);
+    mid ^= clmul64(a0,b1) ^ clmul64(a1,b0);
+    hi ^= clmul64(a1,b1);
+  }
+  [lo as u64, ((lo >> 64) ^ mid) as u64, (hi ^ (mid >> 64)) as u64, (hi >> 64) as u64]
+}
+//
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "pclmulqdq,sse2")]
+fn product_clmul(pairs: &[(u128, u128)]) -> [u64;4] {
+  let (mut lo, mut mid, mut hi) = (_mm_setzero_si128(), _mm_setzero_si128(), _mm_setzero_si128());
+  for &(a, b) in pairs {
+    let a = _mm_set_epi64x((a >> 64) as i64, a as i64);
+    let b = _mm_set_epi64x((b >> 64) as i64, b as i64);
+    lo = _mm_xor_si128(lo,_mm_clmulepi64_si128::<0x00>(a,b));
+    mid = _mm_xor_si128(mid,_mm_xor_si128(_mm_clmulepi64_si128::<0x10>(a,b),_mm_clmulepi64_si128::<0x01>(a,b)));
+    hi = _mm_xor_si128(hi,_mm_clmulepi64_si128::<0x11>(a,b));
+  }
+  let mut words:[u64;6] = [0;6];
+  // SAFETY: 48 writable bytes, storeu has no alignment requirement
+  unsafe {
+    _mm_storeu_si128(words.as_mut_ptr() as *mut __m128i, lo);
+    _mm_storeu_si128(words.as_mut_ptr().add(2) as *mut __m128i, mid);
+    _mm_storeu_si128(words.as_mut_ptr().add(4) as *mut __m128i, hi);
+  }
+  [words[0], words[1]^words[2], words[4]^words[3], words[5]]
+}
+// Montgomery reduction, v * x^-128 mod x^128 + x^127 + x^126 + x^121 + 1
+fn reduce(v: [u64;4]) -> u128 {
+  let [v0, mut v1, mut v2, mut v3] = v;
+  v2 ^= v0 ^ (v0 >> 1) ^ (v0 >> 2) ^ (v0 >> 7);
+  v1 ^= (v0 << 63) ^ (v0 << 62) ^ (v0 << 57);
+  v3 ^= v1 ^ (v1 >> 1) ^ (v1 >> 2) ^ (v1 >> 7);
+  v2 ^= (v1 << 63) ^ (v1 << 62) ^ (v1 << 57);
+  (v2 as u128) | ((v3 as u128) << 64)
+}
+//
+#[derive(Clone)]
+pub struct Polyval {
+  // H, H^2, H^3, H^4
+  powers: [u128;4],
+  acc: u128,
+  clmul: bool,
+}
+//
+impl Polyval {
+  // Uses PCLMULQDQ when the CPU has it
+  pub fn new(h: [u8;16]) -> Polyval {
+    Polyval::build(h,clmul_available())
+  }
+  //
+  pub fn portable(h: [u8;16]) -> Polyval {
+    Polyval::build(h,false)
+  }
+  //
+  fn build(h: [u8;16], clmul: bool) -> Polyval {
+    let mut polyval = Polyval { powers: [u128::from_le_bytes(h);4], acc: 0, clmul };
+    for i in 1..4 {
+      polyval.powers[i] = polyval.dot(&[(polyval.powers[i-1], polyval.powers[0])]);
+    }
+    polyval
+  }
+  // Sum of a*b*x^-128 over the pairs
+  fn dot(&self, pairs: &[(u128, u128)]) -> u128 {
+    #[cfg(target_arch = "x86_64")]
+    if self.clmul {
+      // SAFETY: clmul is only set when the CPU features were detected
+      return reduce(unsafe { product_clmul(pairs) });
+    }
+    reduce(product_soft(pairs))
+  }
+  //
+  pub fn uses_clmul(&self) -> bool {
+    self.clmul
+  }
+}
+//
+impl UniversalHash for Polyval {
+  fn update(&mut self, blocks: &[[u8;16]]) {
+    let mut chunks = blocks.chunks_exact(4);
+    for chunk in chunks.by_ref() {
+      let x:[u128;4] = core::array::from_fn(|i| u128::from_le_bytes(chunk[i]));
+      let [h1, h2, h3, h4] = self.powers;
+      self.acc = self.dot(&[(self.acc ^ x[0], h4), (x[1], h3), (x[2], h2), (x[3], h1)]);
+    }
+    for block in chunks.remainder() {
+      self.acc = self.dot(&[(self.acc ^ u128::from_le_bytes(*block), self.powers[0])]);
+    }
+  }
+  //
+  fn finalize(&self) -> [u8;16] {
+    self.acc.to_le_bytes()
+  }
+}
+//
+#[derive(Clone)]
+pub struct Ghash {
+  polyval: Polyval,
+}
+// RFC 8452 appendix A, GHASH(H, X) = reverse(POLYVAL(mulX(reverse(H)), reverse(X)))
+fn ghash_key(h: [u8;16]) -> [u8;16] {
+  let mut h = h;
+  h.reverse();
+  let v = u128::from_le_bytes(h);
+  let carry = 0u128.wrapping_sub(v >> 127);
+  ((v << 1) ^ (carry & 0xc200_0000_0000_0000_0000_0000_0000_0001)).to_le_bytes()
+}
+//
+impl Ghash {
+  pub fn new(h: [u8;16]) -> Ghash {
+    Ghash { polyval: Polyval::new(ghash_key(h)) }
+  }
+  //
+  pub fn portable(h: [u8;16]) -> Ghash {
+    Ghash { polyval: Polyval::portable(ghash_key(h)) }
+  }
+  //
+  pub fn uses_clmul(&self) -> bool {
+    self.polyval.clmul
+  }
+}
+//
+impl UniversalHash for Ghash {
+  fn update(&mut self, blocks: &[[u8;16]]) {
+    for chunk in blocks.chunks(4) {
+      let mut reversed:[[u8;16];4] = [[0;16];4];
+      for (r, block) in reversed.iter_mut().zip(chunk.iter()) {
+        *r = *block;
+        r.reverse();
+      }
+      self.polyval.update(&reversed[..chunk.len()]);
+    }
+  }
+  //
+  fn finalize(&self) -> [u8;16] {
+    let mut result = self.polyval.finalize();
+    result.reverse();
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unhex;
+    use crate::AesKey;
+    #[test]
+    fn test_polyval() {
+       // RFC 8452 appendix A
+       let h:[u8;16] = unhex("25629347589242761d31f826ba4b757b").try_into().unwrap();
+       let blocks:[[u8;16];2] = [unhex("4f4f95668c83dfb6401762bb2d01a262").try_into().unwrap(), unhex("d1a24ddd2721d006bbe45f20d3c9f362").try_into().unwrap()];
+       // AES-128-GCM-SIV (RFC 8452) with key 00..0f, nonce 00..0b, the 7 blocks of data as AAD and
+       // no plaintext, the tag is the one pyca/cryptography AESGCMSIV returns
+       let data:Vec<u8> = (0..7*16).map(|i| (i*7) as u8).collect();
+       let key = AesKey::new(&(0..16).collect::<Vec<u8>>()).unwrap();
+       let nonce:Vec<u8> = (0..12).collect();
+       let derive = |i:u32| {
+         let mut block:[u8;16] = [0;16];
+         block[..4].copy_from_slice(&i.to_le_bytes());
+         block[4..].copy_from_slice(&nonce);
+         key.encrypt_block(block)
+       };
+       let auth_key:[u8;16] = [&derive(0)[..8], &derive(1)[..8]].concat().try_into().unwrap();
+       let enc_key = AesKey::new(&[&derive(2)[..8], &derive(3)[..8]].concat()).unwrap();
+       let mut length:[u8;16] = [0;16];
+       length[..8].copy_from_slice(&(data.len() as u64*8).to_le_bytes());
+       for (mut polyval, mut aggregated) in [(Polyval::new(h), Polyval::new(auth_key)), (Polyval::portable(h), Polyval::portable(auth_key))] {
+         polyval.update(&blocks);
+         assert_eq!(polyval.finalize().to_vec(),unhex("f7a3b47b846119fae5b7866cf5e5b77e"));
+         aggregated.update_padded(&data);
+         aggregated.update(&[length]);
+         let mut s = aggregated.finalize();
+         s.iter_mut().zip(&nonce).for_each(|(s, n)| *s ^= n);
+         s[15] &= 0x7f;
+         assert_eq!(enc_key.encrypt_block(s).to_vec(),unhex("66b5a32481801ac83fd6f72c91cbb5cf"));
+       }
+    }
+    #[test]
+    fn test_ghash() {
+       // GCM spec test case 2, H = E(K, 0) and GHASH(H, {}, C)
+       let h:[u8;16] = unhex("66e94bd4ef8a2c3b884cfa59ca342b2e").try_into().unwrap();
+       let blocks:[[u8;16];2] = [unhex("0388dace60b6a392f328c2b971b2fe78").try_into().unwrap(), unhex("00000000000000000000000000000080").try_into().unwrap()];
+       // AES-128-GCM with the all-zero key and nonce, the data as AAD and no plaintext, the tags
+       // are the ones pyca/cryptography AESGCM returns, GHASH(H, A, {}) xor E(K, J0)
+       let data:Vec<u8> = (0..7*16).map(|i| (i*7) as u8).collect();
+       let partial:Vec<u8> = (0..21).collect();
+       // E(K, J0) is the tag of GCM spec test case 1
+       let ek0 = unhex("58e2fccefa7e3061367f1d57a4e7455a");
+       let tag = |mut ghash:Ghash, aad:&[u8]| {
+         let mut length:[u8;16] = [0;16];
+         length[..8].copy_from_slice(&(aad.len() as u64*8).to_be_bytes());
+         ghash.update_padded(aad);
+         ghash.update(&[length]);
+         ghash.finalize().iter().zip(&ek0).map(|(g, e)| g^e).collect::<Vec<u8>>()
+       };
+       for mut ghash in [Ghash::new(h), Ghash::portable(h)] {
+         assert_eq!(tag(ghash.clone(),&data),unhex("fc6ad40eaccac0c0ec0f304d528448c8"));
+         assert_eq!(tag(ghash.clone(),&partial),unhex("5e4b7e7d0b927b926e1b9f05bc8a40fe"));
+         ghash.update(&blocks);
+         assert_eq!(ghash.finalize().to_vec(),unhex("f38cbb1ad69223dcc3457ae5b6b0f885"));
+       }
+       assert_eq!(Ghash::new(h).uses_clmul(),clmul_available());
+    }
+}
@@ -0,0 +1,57 @@
+/*
+HMAC
+FIPS 198-1 over SHA-256, SHA-384 and SHA-512
+*/
+use std::convert::TryInto;
+use crate::sha2::{sha256, sha384, sha512};
+// HMAC(K, m) = H((K0 ^ opad) || H((K0 ^ ipad) || m))
+fn hmac(hash:fn(&[u8]) -> Vec<u8>, block_len:usize, key:&[u8], message:&[u8]) -> Vec<u8>{
+  let mut k0:Vec<u8> = if key.len() > block_len { hash(key) } else { key.to_vec() };
+  k0.resize(block_len,0);
+  let mut inner:Vec<u8> = k0.iter().map(|b| b^0x36).collect();
+  inner.extend_from_slice(message);
+  let mut outer:Vec<u8> = k0.iter().map(|b| b^0x5c).collect();
+  outer.extend(hash(&inner));
+  hash(&outer)
+}
+//
+pub fn hmac_sha256(key:&[u8], message:&[u8]) -> [u8;32]{
+  hmac(|m| sha256(m).to_vec(),64,key,message).try_into().unwrap()
+}
+//
+pub fn hmac_sha384(key:&[u8], message:&[u8]) -> [u8;48]{
+  hmac(|m| sha384(m).to_vec(),128,key,message).try_into().unwrap()
+}
+//
+pub fn hmac_sha512(key:&[u8], message:&[u8]) -> [u8;64]{
+  hmac(|m| sha512(m).to_vec(),128,key,message).try_into().unwrap()
+}
+// Tag comparison without an early exit on the first different byte
+pub fn verify_tag(expected:&[u8], tag:&[u8]) -> bool{
+  if expected.len() != tag.len() {
+    return false;
+  }
+  expected.iter().zip(tag.iter()).fold(0, |diff, (a, b)| diff | (a^b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+    #[test]
+    fn test_hmac() {
+       // RFC 4231 test cases 2 and 6
+       let data = b"what do ya want for nothing?";
+       assert_eq!(hex(&hmac_sha256(b"Jefe",data)),"5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+       assert_eq!(hex(&hmac_sha384(b"Jefe",data)),"af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649");
+       assert_eq!(hex(&hmac_sha512(b"Jefe",data)),"164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737");
+       let key:[u8;131]=[0xaa;131];
+       assert_eq!(hex(&hmac_sha256(&key,b"Test Using Larger Than Block-Size Key - Hash Key First")),"60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+    }
+    #[test]
+    fn test_verify_tag() {
+       assert!(verify_tag(&[1,2,3],&[1,2,3]));
+       assert!(!verify_tag(&[1,2,3],&[1,2,4]));
+       assert!(!verify_tag(&[1,2,3],&[1,2]));
+    }
+}
@@ -0,0 +1,753 @@
+/*
+AES implementation in rust
+2023
+Ricardo Hernandez Lopez
+*/
+// The block cipher, key schedules and non-allocating modes build without std
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+use gf::{INV_SBOX, MUL11, MUL13, MUL14, MUL2, MUL3, MUL9, RCON, SBOX};
+use trace::{NoTrace, Observer, Step};
+pub mod aesni;
+pub mod bitslice;
+#[cfg(feature = "std")]
+pub mod cbc;
+#[cfg(feature = "std")]
+pub mod cbc_hmac;
+pub mod cmac;
+pub mod ct;
+pub mod ctr;
+pub mod ecb;
+#[cfg(feature = "std")]
+pub mod fpe;
+pub mod gcm;
+pub mod gf;
+pub mod ghash;
+#[cfg(feature = "std")]
+pub mod hmac;
+#[cfg(feature = "std")]
+pub mod kdf;
+#[cfg(feature = "std")]
+pub mod multi;
+pub mod onthefly;
+#[cfg(feature = "std")]
+pub mod parallel;
+#[cfg(feature = "std")]
+pub mod research;
+#[cfg(feature = "std")]
+pub mod rijndael;
+#[cfg(feature = "std")]
+pub mod sbox;
+#[cfg(feature = "std")]
+pub mod sha2;
+#[cfg(feature = "std")]
+pub mod square;
+pub mod trace;
+pub mod ttable;
+pub mod vpaes;
+pub mod xts;
+//
+fn state2data_block(state:[[u8;4];4]) ->[u8;16] {
+  let mut result:[u8;16]=[0;16];
+  for (k, element) in state.iter().flat_map(|r| r.iter()).enumerate() {
+        result[k] = *element;
+ }
+  result
+}
+//
+// Galois Field (256) Multiplication of two Bytes, tables generated in gf
+fn gmul(a:u8,b:u8) -> u8{
+    if a==2 { return MUL2[b as usize]; } 
+    if a==3 { return MUL3[b as usize]; }
+    if a==9 { return MUL9[b as usize]; }
+    if a==11 { return MUL11[b as usize]; }
+    if a==13 { return MUL13[b as usize]; }
+    if a==14 { MUL14[b as usize] } 
+    else {
+      0 
+    }
+}
+//
+fn mix_columns<const NB: usize>(s:[[u8;4];NB]) -> [[u8; 4]; NB]{
+    let mut result:[[u8; 4]; NB]=[[0;4];NB];
+    for c in 0..NB {
+       result[c][0]= gmul(2,s[c][0])^gmul(3,s[c][1])^s[c][2]^s[c][3];
+       result[c][1]= s[c][0]^ gmul(2,s[c][1])^gmul(3,s[c][2])^s[c][3];
+       result[c][2]= s[c][0]^ s[c][1]^ gmul(2,s[c][2])^gmul(3,s[c][3]);
+       result[c][3]= gmul(3,s[c][0])^s[c][1]^s[c][2] ^gmul(2,s[c][3]);
+    }
+    result
+}
+//
+fn inv_mix_columns<const NB: usize>(s:[[u8;4];NB]) -> [[u8; 4]; NB]{
+    let mut result:[[u8; 4]; NB]=[[0;4];NB];
+    for c in 0..NB {
+       result[c][0]= gmul(0x0e, s[c][0])^gmul(0x0b, s[c][1])^gmul(0x0d,s[c][2])^gmul(0x09,s[c][3]);
+       result[c][1]= gmul(0x09, s[c][0])^gmul(0x0e, s[c][1])^gmul(0x0b,s[c][2])^gmul(0x0d,s[c][3]);
+       result[c][2]= gmul(0x0d, s[c][0])^gmul(0x09, s[c][1])^gmul(0x0e,s[c][2])^gmul(0x0b,s[c][3]);
+       result[c][3]= gmul(0x0b, s[c][0])^gmul(0x0d, s[c][1])^gmul(0x09,s[c][2])^gmul(0x0e,s[c][3]);
+    }
+    result
+}
+//
+fn add_round_key<const NB: usize>(state:[[u8; 4];NB] , w:&[[u8; 4];NB]) -> [[u8;4];NB] {
+     let mut result:[[u8;4];NB]=[[0;4];NB];
+     for c in 0..NB {
+       for r in 0..4 {
+         result[c][r]=state[c][r]^w[c][r];
+       }
+     }
+     result
+}
+//
+fn sub_word(word: [u8; 4]) -> [u8; 4]{
+  sub_word_with(word,&SBOX)
+}
+//
+fn sub_word_with(word: [u8; 4], sbox:&[u8;256]) -> [u8; 4]{
+  let mut result:[u8; 4]=[0;4];
+  for col in 0..4 {
+     result[col]=sbox[word[col] as usize];
+  }
+  result
+}
+//
+fn sub_bytes<const NB: usize>(state:[[u8; 4]; NB]) -> [[u8; 4]; NB] {
+  sub_bytes_with(state,&SBOX)
+}
+//
+fn sub_bytes_with<const NB: usize>(state:[[u8; 4]; NB], sbox:&[u8;256]) -> [[u8; 4]; NB] {
+  let mut result:[[u8; 4]; NB]=[[0;4];NB];
+  for col in 0..NB {
+     result[col]=sub_word_with(state[col],sbox);
+  }
+  result
+}
+//
+fn inv_sub_bytes<const NB: usize>(state:[[u8; 4]; NB]) -> [[u8; 4]; NB] {
+  inv_sub_bytes_with(state,&INV_SBOX)
+}
+//
+fn inv_sub_bytes_with<const NB: usize>(state:[[u8; 4]; NB], inv_sbox:&[u8;256]) -> [[u8; 4]; NB] {
+  let mut result:[[u8; 4]; NB]=[[0;4];NB];
+  for row in 0..4 {
+    for col in 0..NB {
+       result[col][row]=inv_sbox[ state[col][row] as usize ];
+    }
+  }
+  result
+}
+//
+// Row shift offsets C1, C2 and C3 for Nb columns
+fn shift_offsets(nb:usize) -> [usize; 4]{
+    if nb==8 { [0,1,3,4] } else { [0,1,2,3] }
+}
+//
+fn inv_shift_rows<const NB: usize>(state:[[u8;4];NB]) -> [[u8; 4]; NB]{
+    let mut result:[[u8; 4]; NB]=[[0;4];NB];
+    let offset = shift_offsets(NB);
+    for col in 0..NB {
+      for row in 0..4 {
+         result[(col+offset[row])%NB][row]= state[col][row] ;
+      }
+    }
+    result
+}
+//
+fn shift_rows<const NB: usize>(state:[[u8;4];NB]) -> [[u8; 4]; NB]{
+    let mut result:[[u8; 4]; NB]=[[0;4];NB];
+    let offset = shift_offsets(NB);
+    for col in 0..NB {
+      for row in 0..4 {
+         result[col][row]= state[(col+offset[row])%NB][row] ;
+      }
+    }
+    result
+}
+//
+pub fn key_expansion(key:[u8; 32],dec:bool) -> [[[u8; 4] ;4]; 15]{
+  expand_key(&key,dec)
+}
+// Rijndael key schedule for Nb columns, Nk = key.len()/4 and Nr = max(Nk,Nb)+6
+// round keys after Nr are left as zero, the callers check that the key is
+// 16, 24 or 32 bytes
+pub(crate) fn expand_key<const NB: usize>(key:&[u8],dec:bool) -> [[[u8; 4] ;NB]; 15]{ 
+  expand_key_with(key,dec,&SBOX)
+}
+// Key schedule with SubWord done by sbox
+pub(crate) fn expand_key_with<const NB: usize>(key:&[u8],dec:bool,sbox:&[u8;256]) -> [[[u8; 4] ;NB]; 15]{ 
+  let nk = key.len()/4;
+  let nr = nk.max(NB)+6;
+  let mut w:[[u8; 4]; 120] = [[0; 4]; 120];
+  for i in 0..nk {
+    w[i]=[ key[4*i], key[4*i+1], key[4*i+2], key[4*i+3]];
+  }
+  for i in nk..NB*(nr+1) {
+     let mut tmp = [ w[i-1][0], w[i-1][1], w[i-1][2], w[i-1][3] ];
+     if i% nk == 0 {
+       tmp = sub_word_with(  rot_word(tmp), sbox );
+       tmp[0]^=RCON[i/nk];
+     } else if nk>6 && i%nk==4 {
+       tmp = sub_word_with( tmp, sbox );
+     }
+
+    for (x, item) in tmp.iter().enumerate(){
+      w[i][x]=w[i-nk][x] ^ item;
+    }
+  }
+  let mut keys:[[[u8; 4]; NB]; 15] = [[[0; 4] ;NB]; 15];
+  for x in 0..=nr{
+    keys[x].copy_from_slice(&w[NB*x..NB*x+NB]);
+  }
+  if !dec {
+   keys
+  } else {
+    // skip keys 0 and nr
+    for key in keys.iter_mut().take(nr).skip(1){
+      *key=inv_mix_columns(*key);
+    } 
+    keys
+  }
+} 
+// Inverse of the AES key schedule, round_keys holds the round keys from
+// round onwards (two when key_len is 24 or 32), returns the cipher key
+// and its AesKey with every round key
+#[cfg(feature = "std")]
+pub fn invert_key_expansion(key_len:usize, round:usize, round_keys:&[u8]) -> Result<(Vec<u8>, AesKey), &'static str>{
+  if key_len != 16 && key_len != 24 && key_len != 32 {
+    return Err("key must be 128, 192 or 256 bits");
+  }
+  let nk = key_len/4;
+  let nr = nk+6;
+  if round_keys.is_empty() || !round_keys.len().is_multiple_of(16) || round_keys.len() < key_len {
+    return Err("round keys must be whole 16 byte keys covering the key length");
+  }
+  if round+round_keys.len()/16 > nr+1 {
+    return Err("round keys go past the last round");
+  }
+  let mut w:[[u8; 4]; 60] = [[0; 4]; 60];
+  let start = 4*round;
+  for (i, word) in round_keys.chunks_exact(4).take(nk).enumerate() {
+    w[start+i]=[word[0], word[1], word[2], word[3]];
+  }
+  // w[i-Nk] = w[i] ^ f(w[i-1]) run backwards
+  for i in (nk..start+nk).rev() {
+     let mut tmp = w[i-1];
+     if i% nk == 0 {
+       tmp = sub_word( rot_word(tmp) );
+       tmp[0]^=RCON[i/nk];
+     } else if nk>6 && i%nk==4 {
+       tmp = sub_word( tmp );
+     }
+    for (x, item) in tmp.iter().enumerate(){
+      w[i-nk][x]=w[i][x] ^ item;
+    }
+  }
+  let key:Vec<u8> = w[..nk].concat();
+  let aes = AesKey::new(&key)?;
+  if aes.enc[round..].concat().concat()[..round_keys.len()] != *round_keys {
+    return Err("round keys are not from the same key schedule");
+  }
+  Ok((key,aes))
+}
+//
+fn rot_word(w:[u8;4]) -> [u8; 4]{
+  let mut result:[u8;4]=[0;4];
+  result[3]=w[0];
+  result[0]=w[1];
+  result[1]=w[2];
+  result[2]=w[3];
+  result
+}
+//
+fn create_state<const NB: usize>(data:&[u8]) ->[[u8;4];NB]{
+   let mut state:[[u8; 4]; NB] = [[0; 4]; NB];
+   for (c, item) in data.iter().enumerate() {
+     state[c/4][c%4]= *item;
+   }
+   state   
+}
+//
+// Implementation behind AesKey, every backend gives the same output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    // S-box and GF(2^8) lookup tables
+    #[default]
+    Table,
+    // no table lookups indexed by key or data, see ct
+    ConstantTime,
+    // up to four blocks at once on 64-bit words, see bitslice
+    Bitsliced,
+    // x86 AES instructions, see aesni
+    AesNi,
+    // four 1 KiB lookup tables per direction, fast but not constant-time
+    TTable,
+    // SSSE3 vector permutes, constant-time, see vpaes
+    Vpaes,
+}
+//
+impl Backend {
+    // Fastest constant-time backend this CPU supports, the bitsliced one
+    // when there is neither AES-NI nor SSSE3, both fallbacks outrun Table
+    pub fn detect() -> Backend {
+        if aesni::available() {
+            Backend::AesNi
+        } else if vpaes::available() {
+            Backend::Vpaes
+        } else {
+            Backend::Bitsliced
+        }
+    }
+}
+//
+// Expanded encryption and decryption schedules for AES-128, AES-192 or AES-256
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AesKey {
+    pub rounds: usize,
+    pub enc: [[[u8; 4] ;4]; 15],
+    pub dec: [[[u8; 4] ;4]; 15],
+    pub backend: Backend,
+    // enc in the bitsliced layout, only set for Backend::Bitsliced
+    pub(crate) planes: [[u64; 8]; 15],
+}
+//
+impl AesKey {
+    pub fn new(key: &[u8]) -> Result<AesKey, &'static str> {
+        AesKey::with_backend(key,Backend::Table)
+    }
+    // The key schedule is also computed by the backend, AesNi falls back
+    // to Table and Vpaes to ConstantTime when the CPU does not have them
+    pub fn with_backend(key: &[u8], backend: Backend) -> Result<AesKey, &'static str> {
+        if key.len() != 16 && key.len() != 24 && key.len() != 32 {
+            return Err("key must be 128, 192 or 256 bits");
+        }
+        let rounds = key.len()/4+6;
+        let (enc, dec, backend) = match backend {
+            Backend::AesNi if aesni::available() => {
+                let (enc, dec) = aesni::expand_key(key);
+                (enc, dec, backend)
+            },
+            Backend::Table | Backend::AesNi => (expand_key(key,false), expand_key(key,true), Backend::Table),
+            Backend::TTable => (expand_key(key,false), expand_key(key,true), backend),
+            Backend::ConstantTime | Backend::Bitsliced => (ct::expand_key(key,false), ct::expand_key(key,true), backend),
+            // constant-time key schedule, ConstantTime without SSSE3
+            Backend::Vpaes => (ct::expand_key(key,false), ct::expand_key(key,true),
+                if vpaes::available() { backend } else { Backend::ConstantTime }),
+        };
+        let planes = if backend == Backend::Bitsliced { bitslice::round_keys(&enc) } else { [[0; 8]; 15] };
+        Ok(AesKey { rounds, enc, dec, backend, planes })
+    }
+    pub fn encrypt_block(&self, block: [u8;16]) -> [u8;16] {
+        match self.backend {
+            Backend::Table => state2data_block(cipher(&block,&self.enc,self.rounds,&mut NoTrace)),
+            Backend::ConstantTime => ct::encrypt_block(block,&self.enc,self.rounds),
+            Backend::Bitsliced => {
+                let mut blocks = [block];
+                bitslice::encrypt_blocks(&self.planes,self.rounds,&mut blocks);
+                blocks[0]
+            },
+            Backend::AesNi => aesni::encrypt_block(block,&self.enc,self.rounds),
+            Backend::TTable => ttable::encrypt_block(block,&self.enc,self.rounds),
+            Backend::Vpaes => vpaes::encrypt_block(block,&self.enc,self.rounds),
+        }
+    }
+    pub fn decrypt_block(&self, block: [u8;16]) -> [u8;16] {
+        match self.backend {
+            Backend::Table => state2data_block(inv_cipher(&block,&self.dec,self.rounds,&mut NoTrace)),
+            Backend::ConstantTime => ct::decrypt_block(block,&self.dec,self.rounds),
+            Backend::Bitsliced => {
+                let mut blocks = [block];
+                bitslice::decrypt_blocks(&self.planes,self.rounds,&mut blocks);
+                blocks[0]
+            },
+            Backend::AesNi => aesni::decrypt_block(block,&self.dec,self.rounds),
+            Backend::TTable => ttable::decrypt_block(block,&self.dec,self.rounds),
+            Backend::Vpaes => vpaes::decrypt_block(block,&self.dec,self.rounds),
+        }
+    }
+    // Blocks per interleaved group of encrypt_blocks and decrypt_blocks
+    pub fn lanes(&self) -> usize {
+        if self.backend == Backend::AesNi { 8 } else { 4 }
+    }
+    // Bulk path for the modes, in place, groups of lanes() blocks go through
+    // each round together with the borrowed schedule
+    pub fn encrypt_blocks(&self, blocks: &mut [[u8;16]]) {
+        match self.backend {
+            Backend::Bitsliced => blocks.chunks_mut(4).for_each(|chunk| bitslice::encrypt_blocks(&self.planes,self.rounds,chunk)),
+            Backend::Table => blocks.chunks_mut(4).for_each(|chunk| encrypt_lanes(chunk,&self.enc,self.rounds)),
+            Backend::AesNi => blocks.chunks_mut(8).for_each(|chunk| aesni::encrypt_blocks(chunk,&self.enc,self.rounds)),
+            Backend::TTable => blocks.chunks_mut(4).for_each(|chunk| ttable::encrypt_blocks(chunk,&self.enc,self.rounds)),
+            Backend::Vpaes => vpaes::encrypt_blocks(blocks,&self.enc,self.rounds),
+            _ => blocks.iter_mut().for_each(|block| *block = self.encrypt_block(*block)),
+        }
+    }
+    //
+    pub fn decrypt_blocks(&self, blocks: &mut [[u8;16]]) {
+        match self.backend {
+            Backend::Bitsliced => blocks.chunks_mut(4).for_each(|chunk| bitslice::decrypt_blocks(&self.planes,self.rounds,chunk)),
+            Backend::Table => blocks.chunks_mut(4).for_each(|chunk| decrypt_lanes(chunk,&self.dec,self.rounds)),
+            Backend::AesNi => blocks.chunks_mut(8).for_each(|chunk| aesni::decrypt_blocks(chunk,&self.dec,self.rounds)),
+            Backend::TTable => blocks.chunks_mut(4).for_each(|chunk| ttable::decrypt_blocks(chunk,&self.dec,self.rounds)),
+            Backend::Vpaes => vpaes::decrypt_blocks(blocks,&self.dec,self.rounds),
+            _ => blocks.iter_mut().for_each(|block| *block = self.decrypt_block(*block)),
+        }
+    }
+    // Round by round tracing always runs the table implementation
+    pub fn encrypt_block_with<O: Observer>(&self, block: [u8;16], observer: &mut O) -> [u8;16] {
+        state2data_block(cipher(&block,&self.enc,self.rounds,observer))
+    }
+    pub fn decrypt_block_with<O: Observer>(&self, block: [u8;16], observer: &mut O) -> [u8;16] {
+        state2data_block(inv_cipher(&block,&self.dec,self.rounds,observer))
+    }
+}
+// AES-256
+pub fn encrypt_block(block:[u8;16], keys:[[[u8; 4] ;4]; 15]) -> [u8;16]{
+  state2data_block(cipher(&block,&keys,14,&mut NoTrace))
+}
+//
+pub fn decrypt_block(block:[u8;16], keys:[[[u8; 4] ;4]; 15]) -> [u8;16]{
+  state2data_block(inv_cipher(&block,&keys,14,&mut NoTrace))
+}
+//
+fn cipher<const NB: usize, O: Observer>(block:&[u8], keys:&[[[u8; 4] ;NB]; 15], nr:usize, observer:&mut O) -> [[u8;4];NB]{
+   cipher_with(block,keys,nr,&SBOX,observer)
+}
+//
+fn cipher_with<const NB: usize, O: Observer>(block:&[u8], keys:&[[[u8; 4] ;NB]; 15], nr:usize, sbox:&[u8;256], observer:&mut O) -> [[u8;4];NB]{
+   let mut state = create_state(block); 
+   observer.observe(0,Step::Input,&state);
+   observer.round_key(0,&keys[0]);
+   state=add_round_key(state,&keys[0]);
+   observer.observe(0,Step::AddRoundKey,&state);
+   for (i, key) in keys.iter().enumerate().take(nr).skip(1) {
+     state = sub_bytes_with(state,sbox);
+     observer.observe(i,Step::SubBytes,&state);
+     state = shift_rows(state);
+     observer.observe(i,Step::ShiftRows,&state);
+     state = mix_columns(state);
+     observer.observe(i,Step::MixColumns,&state);
+     observer.round_key(i,key);
+     state = add_round_key(state,key);
+     observer.observe(i,Step::AddRoundKey,&state);
+   }
+   state = sub_bytes_with(state,sbox);
+   observer.observe(nr,Step::SubBytes,&state);
+   state = shift_rows(state);
+   observer.observe(nr,Step::ShiftRows,&state);
+   observer.round_key(nr,&keys[nr]);
+   state = add_round_key(state,&keys[nr]);
+   observer.observe(nr,Step::AddRoundKey,&state);
+   observer.observe(nr,Step::Output,&state);
+   state
+}
+// Equivalent inverse cipher, the rounds are numbered as in the encryption
+fn inv_cipher<const NB: usize, O: Observer>(block:&[u8], keys:&[[[u8; 4] ;NB]; 15], nr:usize, observer:&mut O) -> [[u8;4];NB]{
+  inv_cipher_with(block,keys,nr,&INV_SBOX,observer)
+}
+//
+fn inv_cipher_with<const NB: usize, O: Observer>(block:&[u8], keys:&[[[u8; 4] ;NB]; 15], nr:usize, inv_sbox:&[u8;256], observer:&mut O) -> [[u8;4];NB]{
+  let mut state = create_state(block);
+  observer.observe(nr,Step::Input,&state);
+  observer.round_key(nr,&keys[nr]);
+  state = add_round_key(state,&keys[nr]);
+  observer.observe(nr,Step::AddRoundKey,&state);
+  for (i, key) in keys.iter().enumerate().take(nr).skip(1).rev() {
+    state = inv_sub_bytes_with(state,inv_sbox);
+    observer.observe(i,Step::InvSubBytes,&state);
+    state = inv_shift_rows(state);
+    observer.observe(i,Step::InvShiftRows,&state);
+    state = inv_mix_columns(state);
+    observer.observe(i,Step::InvMixColumns,&state);
+    observer.round_key(i,key);
+    state = add_round_key(state,key);
+    observer.observe(i,Step::AddRoundKey,&state);
+  } 
+  state = inv_sub_bytes_with(state,inv_sbox);
+  observer.observe(0,Step::InvSubBytes,&state);
+  state = inv_shift_rows(state);
+  observer.observe(0,Step::InvShiftRows,&state);
+  observer.round_key(0,&keys[0]);
+  state = add_round_key(state,&keys[0]);
+  observer.observe(0,Step::AddRoundKey,&state);
+  observer.observe(0,Step::Output,&state);
+  state
+}
+// Table cipher on up to four blocks, each round is applied to all of them
+// before the next one
+fn encrypt_lanes(blocks:&mut [[u8;16]], keys:&[[[u8; 4] ;4]; 15], nr:usize){
+  let mut states:[[[u8;4];4];4] = [[[0;4];4];4];
+  for (state, block) in states.iter_mut().zip(blocks.iter()) {
+    *state = add_round_key(create_state(block),&keys[0]);
+  }
+  let states = &mut states[..blocks.len()];
+  for key in keys.iter().take(nr).skip(1) {
+    for state in states.iter_mut() {
+      *state = add_round_key(mix_columns(shift_rows(sub_bytes(*state))),key);
+    }
+  }
+  for (state, block) in states.iter().zip(blocks.iter_mut()) {
+    *block = state2data_block(add_round_key(shift_rows(sub_bytes(*state)),&keys[nr]));
+  }
+}
+// Equivalent inverse cipher with the dec schedule
+fn decrypt_lanes(blocks:&mut [[u8;16]], keys:&[[[u8; 4] ;4]; 15], nr:usize){
+  let mut states:[[[u8;4];4];4] = [[[0;4];4];4];
+  for (state, block) in states.iter_mut().zip(blocks.iter()) {
+    *state = add_round_key(create_state(block),&keys[nr]);
+  }
+  let states = &mut states[..blocks.len()];
+  for key in keys.iter().take(nr).skip(1).rev() {
+    for state in states.iter_mut() {
+      *state = add_round_key(inv_mix_columns(inv_shift_rows(inv_sub_bytes(*state))),key);
+    }
+  }
+  for (state, block) in states.iter().zip(blocks.iter_mut()) {
+    *block = state2data_block(add_round_key(inv_shift_rows(inv_sub_bytes(*state)),&keys[0]));
+  }
+}
+// Same output as aes_encrypt_with, through the bulk path of an AES-256 key
+#[cfg(feature = "std")]
+pub fn aes_encrypt(mut input:Vec<u8>, z:[u8;32],size:usize) -> Vec<u8>{
+  let padding:usize = size%16;
+  input.extend(std::iter::repeat_n(0x80,padding+16));
+  let mut blocks:Vec<[u8;16]> = input[..(size+padding+16)/16*16].chunks_exact(16).map(|b| b.try_into().unwrap()).collect();
+  AesKey::new(&z).unwrap().encrypt_blocks(&mut blocks);
+  blocks.concat()
+}
+//
+#[cfg(feature = "std")]
+pub fn aes_decrypt(mut input:Vec<u8>, z:[u8;32],size:usize) -> Vec<u8>{
+  let padding:usize = size%16;
+  input.extend(std::iter::repeat_n(0x80,padding));
+  let mut blocks:Vec<[u8;16]> = input[..(size+padding)/16*16].chunks_exact(16).map(|b| b.try_into().unwrap()).collect();
+  AesKey::new(&z).unwrap().decrypt_blocks(&mut blocks);
+  blocks.concat()
+}
+// Index in the decrypted block of byte c for files written before the
+// state followed FIPS-197, create_state used to start a new row after
+// bytes 0, 4, 8 and 12
+#[cfg(feature = "std")]
+fn legacy_position(c:usize) -> usize {
+  let row = if c == 0 { 0 } else { ((c-1)/4+1)%4 };
+  4*row+c%4
+}
+// aes_decrypt for ciphertext written by aes e with the old byte order
+#[cfg(feature = "std")]
+pub fn aes_decrypt_legacy(input:Vec<u8>, z:[u8;32],size:usize) -> Vec<u8>{
+  let output = aes_decrypt(input,z,size);
+  output.chunks_exact(16).flat_map(|block| (0..16).map(move |c| block[legacy_position(c)])).collect()
+}
+//
+#[cfg(feature = "std")]
+pub fn aes_encrypt_with<O: Observer>(mut input:Vec<u8>, z:[u8;32],size:usize, observer:&mut O) -> Vec<u8>{
+   let mut result:Vec<u8> = vec![];
+   let mut block:[u8;16];
+   let padding:usize = size%16;
+   let mut w:usize = size+padding+16;
+   for _i in 0..padding+16{
+       let x:u8 = 0x80;
+       input.push(x);
+   }
+   let keys=key_expansion(z,false);
+   let mut g = 0;
+   loop {
+     if w<16 { return result; }
+     block = input[g..(g+16)].try_into().unwrap(); // block of 16 bytes = 128 bits
+     let last = state2data_block(cipher(&block,&keys,14,observer));
+     result.extend(last.to_vec().iter().copied());
+     w-=16;
+     g+=16;
+  }
+}
+//
+#[cfg(feature = "std")]
+pub fn aes_decrypt_with<O: Observer>(mut input:Vec<u8>, z:[u8;32],size:usize, observer:&mut O) -> Vec<u8>{
+  let mut result:Vec<u8> = vec![];
+  let mut block:[u8;16];
+  let padding:usize = size%16;
+  let mut w:usize= size+padding;
+  for _i in 0..padding{
+       let x:u8 = 0x80;
+       input.push(x);
+  }
+  let keys = key_expansion(z,true);
+  let mut g = 0;
+  loop {
+    if w<16 { return result; }
+    block = input[g..(g+16)].try_into().unwrap(); // block of 16 bytes = 128 bits
+    let last = state2data_block(inv_cipher(&block,&keys,14,observer));
+    result.extend(last.to_vec().iter().copied());
+    w-=16;
+    g+=16;
+  }
+}
+
+// Hex helpers shared by the test modules
+#[cfg(test)]
+pub(crate) mod testing {
+    pub fn hex(bytes:&[u8]) -> String {
+       bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+    pub fn unhex(hex:&str) -> Vec<u8> {
+       (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i+2],16).unwrap()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_create_state() {
+       let block:[u8;16]=[0,1,2,3,0,1,2,3,0,1,2,3,0,1,2,3];
+       let state = create_state(&block);
+       let expected:[[u8;4];4]=[[0,1,2,3],[0,1,2,3],[0,1,2,3],[0,1,2,3]];
+       assert_eq!(state,expected);
+    }
+   #[test]
+   fn test_state2data_block() {
+       let state:[[u8;4];4]=[[0,1,2,3],[0,1,2,3],[0,1,2,3],[0,1,2,3]];
+       let expected:[u8;16]=[0,1,2,3,0,1,2,3,0,1,2,3,0,1,2,3];
+       let last = state2data_block(state);
+       assert_eq!(last,expected);
+    }
+   #[test]
+   fn test_rot_word() {
+       let word:[u8;4]=[0,1,2,3];
+       let expected:[u8;4]=[1,2,3,0];
+       let result = rot_word(word);
+       assert_eq!(result,expected);
+    }
+   #[test]
+   fn test_mix_unmix_columns() {
+       // https://en.wikipedia.org/wiki/Rijndael_MixColumns#Test_vectors_for_MixColumn()
+       let state:[[u8;4];4]=[[0xdb, 0x13, 0x53, 0x45],[0xf2, 0x0a, 0x22, 0x5c],[0x01, 0x01, 0x01, 0x01],[0xc6,0xc6,0xc6,0xc6]];
+       let mix = mix_columns(state);
+       let expected = [[142, 77, 161, 188],[159, 220, 88, 157],[1,1,1,1],[198,198,198,198]];
+       let unmix = inv_mix_columns(mix);
+       assert_eq!(mix,expected);
+       assert_eq!(unmix,state);
+    }
+   #[test]
+   fn test_shift_rows() {
+       let state:[[u8;4];4]=[[0,1,2,3],[0,1,2,3],[0,1,2,3],[0,1,2,3]];
+       let mix = shift_rows(state);
+       let unmix = inv_shift_rows(mix);
+       assert_eq!(state,unmix);
+    }
+   #[test]
+   fn test_encrypt_decrypt_block() {
+       // FIPS-197 Appendix C.3 AES-256
+       let key:[u8;32]= [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31];
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       let expected:[u8;16]=[0x8e,0xa2,0xb7,0xca,0x51,0x67,0x45,0xbf,0xea,0xfc,0x49,0x90,0x4b,0x49,0x60,0x89];
+       let cipher = encrypt_block(plain,key_expansion(key,false));
+       assert_eq!(cipher,expected);
+       assert_eq!(decrypt_block(cipher,key_expansion(key,true)),plain);
+    }
+   #[test]
+   fn test_aes_key_sizes() {
+       // FIPS-197 Appendix C.1 AES-128 and C.2 AES-192
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       let key_128:[u8;16]=[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+       let aes_128 = AesKey::new(&key_128).unwrap();
+       let expected:[u8;16]=[0x69,0xc4,0xe0,0xd8,0x6a,0x7b,0x04,0x30,0xd8,0xcd,0xb7,0x80,0x70,0xb4,0xc5,0x5a];
+       assert_eq!(aes_128.encrypt_block(plain),expected);
+       assert_eq!(aes_128.decrypt_block(expected),plain);
+       let key_192:[u8;24]=[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23];
+       let aes_192 = AesKey::new(&key_192).unwrap();
+       let expected:[u8;16]=[0xdd,0xa9,0x7c,0xa4,0x86,0x4c,0xdf,0xe0,0x6e,0xaf,0x70,0xa0,0xec,0x0d,0x71,0x91];
+       assert_eq!(aes_192.encrypt_block(plain),expected);
+       assert_eq!(aes_192.decrypt_block(expected),plain);
+       assert!(AesKey::new(&[0;20]).is_err());
+       let ct_128 = AesKey::with_backend(&key_128,Backend::ConstantTime).unwrap();
+       assert_eq!(ct_128.encrypt_block(plain),aes_128.encrypt_block(plain));
+       assert_eq!(ct_128.decrypt_block(ct_128.encrypt_block(plain)),plain);
+    }
+   #[test]
+   fn test_aes_encrypt_decrypt() {
+       let key:[u8;32]= [7; 32];
+       let input = b"The Advanced Encryption Standard".to_vec();
+       let size = input.len()-1;
+       let cipher = aes_encrypt(input.clone(),key,size);
+       let plain = aes_decrypt(cipher.clone(),key,cipher.len()-1);
+       assert_eq!(plain[..input.len()],input[..]);
+       assert_eq!(cipher,aes_encrypt_with(input.clone(),key,size,&mut NoTrace));
+       assert_eq!(plain,aes_decrypt_with(cipher.clone(),key,cipher.len()-1,&mut NoTrace));
+    }
+   #[test]
+   fn test_encrypt_blocks_lanes() {
+       // whole groups of lanes and a remainder for every backend
+       let key:Vec<u8> = (0..24).collect();
+       let plain:Vec<[u8;16]> = (0..19).map(|i| [i as u8; 16]).collect();
+       for backend in [Backend::Table, Backend::ConstantTime, Backend::Bitsliced, Backend::AesNi, Backend::TTable, Backend::Vpaes] {
+         let aes = AesKey::with_backend(&key,backend).unwrap();
+         let mut blocks = plain.clone();
+         aes.encrypt_blocks(&mut blocks);
+         let expected:Vec<[u8;16]> = plain.iter().map(|b| aes.encrypt_block(*b)).collect();
+         assert_eq!(blocks,expected);
+         aes.decrypt_blocks(&mut blocks);
+         assert_eq!(blocks,plain);
+       }
+       // never the table backend, the key keeps the detected one
+       let detected = Backend::detect();
+       assert_ne!(detected,Backend::Table);
+       assert_eq!(AesKey::with_backend(&key,detected).unwrap().backend,detected);
+    }
+   #[test]
+   fn test_shift_rows_nb() {
+       // Nb = 6 shifts rows by 1, 2, 3 and Nb = 8 by 1, 3, 4
+       let state:[[u8;4];6]=[[0,10,20,30],[1,11,21,31],[2,12,22,32],[3,13,23,33],[4,14,24,34],[5,15,25,35]];
+       let shifted = shift_rows(state);
+       assert_eq!(shifted[0],[0,11,22,33]);
+       assert_eq!(inv_shift_rows(shifted),state);
+       let state:[[u8;4];8]=[[0,10,20,30],[1,11,21,31],[2,12,22,32],[3,13,23,33],[4,14,24,34],[5,15,25,35],[6,16,26,36],[7,17,27,37]];
+       let shifted = shift_rows(state);
+       assert_eq!(shifted[0],[0,11,23,34]);
+       assert_eq!(shifted[7],[7,10,22,33]);
+       assert_eq!(inv_shift_rows(shifted),state);
+    }
+   #[test]
+   fn test_invert_key_expansion() {
+       for len in [16,24,32] {
+         let key:Vec<u8> = (0..len as u8).map(|b| b.wrapping_mul(37)^0x5a).collect();
+         let keys = expand_key::<4>(&key,false);
+         let nr = len/4+6;
+         let needed = if len == 16 { 1 } else { 2 };
+         for round in 0..=nr+1-needed {
+           let round_keys = keys[round..round+needed].concat().concat();
+           let (recovered,schedule) = invert_key_expansion(len,round,&round_keys).unwrap();
+           assert_eq!(recovered,key);
+           assert_eq!(schedule.enc,keys);
+         }
+       }
+       // FIPS-197 Appendix A.1, last round key
+       let last:[u8;16]=[0xd0,0x14,0xf9,0xa8,0xc9,0xee,0x25,0x89,0xe1,0x3f,0x0c,0xc8,0xb6,0x63,0x0c,0xa6];
+       let (key,_) = invert_key_expansion(16,10,&last).unwrap();
+       assert_eq!(key,[0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c]);
+       assert!(invert_key_expansion(16,11,&last).is_err());
+       assert!(invert_key_expansion(32,10,&last).is_err());
+       let mut wrong = expand_key::<4>(&[7;24],false)[3..5].concat().concat();
+       wrong[31]^=1;
+       assert!(invert_key_expansion(24,3,&wrong).is_err());
+    }
+    #[test]
+   fn test_aes_decrypt_legacy() {
+       // written by aes e before the state followed FIPS-197
+       let key = *b"0123456789abcdef0123456789abcdef";
+       let cipher:Vec<u8> = vec![0x2c,0xf5,0x19,0xbe,0x8e,0x79,0xf2,0xb1,0x67,0x3a,0x44,0x66,0xaa,0xdc,0x43,0xd1,0x83,0xe5,0x46,0x34,0x1a,0x9e,0x5e,0x23,0xf1,0x3d,0x4c,0x43,0xdd,0xdd,0x3c,0xfe,0x56,0x29,0xd1,0x48,0x06,0xa5,0x63,0x14,0x69,0x67,0x9a,0xc2,0x1f,0x0d,0x35,0x54];
+       let plain = aes_decrypt_legacy(cipher.clone(),key,cipher.len()-1);
+       assert_eq!(plain[..32],b"The Advanced Encryption Standard"[..]);
+       assert_ne!(aes_decrypt(cipher.clone(),key,cipher.len()-1)[..32],plain[..32]);
+    }
+   #[test]
+   fn test_key_expansion() {
+       let key:[u8;32]= [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31];
+       let keys = [[[0, 1, 2, 3], [4, 5, 6, 7], [8, 9, 10, 11], [12, 13, 14, 15]], [[16, 17, 18, 19], [20, 21, 22, 23], [24, 25, 26, 27], [28, 29, 30, 31]], [[165, 115, 194, 159], [161, 118, 196, 152], [169, 127, 206, 147], [165, 114, 192, 156]], [[22, 81, 168, 205], [2, 68, 190, 218], [26, 93, 164, 193], [6, 64, 186, 222]], [[174, 135, 223, 240], [15, 241, 27, 104], [166, 142, 213, 251], [3, 252, 21, 103]], [[109, 225, 241, 72], [111, 165, 79, 146], [117, 248, 235, 83], [115, 184, 81, 141]], [[198, 86, 130, 127], [201, 167, 153, 23], [111, 41, 76, 236], [108, 213, 89, 139]], [[61, 226, 58, 117], [82, 71, 117, 231], [39, 191, 158, 180], [84, 7, 207, 57]], [[11, 220, 144, 95], [194, 123, 9, 72], [173, 82, 69, 164], [193, 135, 28, 47]], [[69, 245, 166, 96], [23, 178, 211, 135], [48, 13, 77, 51], [100, 10, 130, 10]], [[124, 207, 247, 28], [190, 180, 254, 84], [19, 230, 187, 240], [210, 97, 167, 223]], [[240, 26, 250, 254], [231, 168, 41, 121], [215, 165, 100, 74], [179, 175, 230, 64]], [[37, 65, 254, 113], [155, 245, 0, 37], [136, 19, 187, 213], [90, 114, 28, 10]], [[78, 90, 102, 153], [169, 242, 79, 224], [126, 87, 43, 170], [205, 248, 205, 234]], [[36, 252, 121, 204], [191, 9, 121, 233], [55, 26, 194, 60], [109, 104, 222, 54]]];
+       let expanded = key_expansion(key,false);
+       println!("{:?}",expanded);
+       assert_eq!(expanded,keys);
+       let keys_d =[[[0, 1, 2, 3], [4, 5, 6, 7], [8, 9, 10, 11], [12, 13, 14, 15]], [[26, 31, 24, 29], [30, 27, 28, 25], [18, 23, 16, 21], [22, 19, 20, 17]], [[42, 40, 64, 201], [36, 35, 76, 192], [38, 36, 76, 197], [32, 39, 72, 196]], [[127, 215, 133, 15], [97, 204, 153, 22], [115, 219, 137, 3], [101, 200, 157, 18]], [[21, 198, 104, 189], [49, 229, 36, 125], [23, 193, 104, 184], [55, 230, 32, 124]], [[174, 213, 88, 22], [207, 25, 193, 0], [188, 194, 72, 3], [217, 10, 213, 17]], [[222, 105, 64, 154], [239, 140, 100, 231], [248, 77, 12, 95], [207, 171, 44, 35]], [[248, 95, 196, 243], [55, 70, 5, 243], [139, 132, 77, 240], [82, 142, 152, 225]], [[60, 166, 151, 21], [211, 42, 243, 242], [43, 103, 255, 173], [228, 204, 211, 142]], [[116, 218, 123, 163], [67, 156, 126, 80], [200, 24, 51, 160], [154, 150, 171, 65]], [[181, 112, 142, 19], [102, 90, 125, 225], [77, 61, 130, 76], [169, 241, 81, 194]], [[200, 163, 5, 128], [139, 63, 123, 208], [67, 39, 72, 112], [217, 177, 227, 49]], [[94, 22, 72, 235], [56, 76, 53, 10], [117, 113, 183, 70], [220, 128, 230, 132]], [[52, 241, 209, 255], [191, 206, 170, 47], [252, 233, 226, 95], [37, 88, 1, 110]], [[36, 252, 121, 204], [191, 9, 121, 233], [55, 26, 194, 60], [109, 104, 222, 54]]];
+      let expanded_d = key_expansion(key,true);
+      println!("{:?}",expanded_d);
+       assert_eq!(expanded_d,keys_d);
+    }
+}
@@ -0,0 +1,94 @@
+/*
+Multi-buffer encryption
+Many independent messages, each with its own key and nonce, share the
+cipher lanes: the counter blocks of all of them are gathered into groups
+that go through the rounds together, eight lanes with AES-NI and four with
+the bitsliced backend, other backends take the blocks one at a time
+*/
+use crate::{aesni, bitslice, AesKey, Backend};
+// One message of a multi-buffer call, the nonce is the initial counter
+// block for CTR and the IV for GCM
+#[derive(Debug, Clone, Copy)]
+pub struct Message<'a> {
+  pub key: &'a AesKey,
+  pub nonce: &'a [u8],
+  pub aad: &'a [u8],
+  pub data: &'a [u8],
+}
+// Encrypts blocks[i] with keys[i] in place, runs of keys with the same
+// lane backend are interleaved
+pub fn encrypt_blocks_multi(keys: &[&AesKey], blocks: &mut [[u8;16]]) {
+  assert_eq!(keys.len(),blocks.len(),"one key per block");
+  let mut start = 0;
+  while start < blocks.len() {
+    let first = keys[start];
+    let lanes = match first.backend { Backend::AesNi => 8, Backend::Bitsliced => 4, _ => 1 };
+    let mut end = start+1;
+    while end < blocks.len() && end-start < lanes && keys[end].backend == first.backend
+        && (first.backend == Backend::AesNi || keys[end].rounds == first.rounds) {
+      end += 1;
+    }
+    let (group, lane_keys) = (&mut blocks[start..end], &keys[start..end]);
+    match first.backend {
+      Backend::AesNi => aesni::encrypt_blocks_multi(group,lane_keys),
+      Backend::Bitsliced if group.len() > 1 => bitslice::encrypt_blocks(&bitslice::round_keys_multi(lane_keys),first.rounds,group),
+      _ => group[0] = first.encrypt_block(group[0]),
+    }
+    start = end;
+  }
+}
+// XORs into outputs[i] the keystream of keys[i], block j of message i uses
+// the counter block counter(i, j), every pass takes enough blocks from each
+// unfinished message to fill the lanes
+pub fn xor_keystream_multi(keys: &[&AesKey], counter: impl Fn(usize, usize) -> [u8;16], outputs: &mut [Vec<u8>]) {
+  let lanes = keys.iter().map(|key| key.lanes()).max().unwrap_or(1);
+  let mut next:Vec<usize> = vec![0; outputs.len()];
+  let mut active:Vec<usize> = (0..outputs.len()).filter(|&i| !outputs[i].is_empty()).collect();
+  let mut pass:Vec<(usize, usize)> = vec![];
+  while !active.is_empty() {
+    let take = lanes.div_ceil(active.len());
+    pass.clear();
+    for &i in active.iter() {
+      let blocks = outputs[i].len().div_ceil(16);
+      pass.extend((next[i]..blocks.min(next[i]+take)).map(|j| (i, j)));
+      next[i] = blocks.min(next[i]+take);
+    }
+    for group in pass.chunks(8) {
+      let mut blocks:[[u8;16]; 8] = [[0; 16]; 8];
+      let mut group_keys:[&AesKey; 8] = [keys[group[0].0]; 8];
+      for ((block, key), &(i, j)) in blocks.iter_mut().zip(group_keys.iter_mut()).zip(group.iter()) {
+        *block = counter(i,j);
+        *key = keys[i];
+      }
+      encrypt_blocks_multi(&group_keys[..group.len()],&mut blocks[..group.len()]);
+      for (block, &(i, j)) in blocks.iter().zip(group.iter()) {
+        for (byte, k) in outputs[i][16*j..].iter_mut().zip(block.iter()) {
+          *byte ^= k;
+        }
+      }
+    }
+    active.retain(|&i| next[i] < outputs[i].len().div_ceil(16));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_encrypt_blocks_multi() {
+       // mixed key sizes and backends against single blocks with the table backend
+       let raw:Vec<Vec<u8>> = [16, 32, 24, 16, 16, 32, 16, 24, 16, 16].iter().enumerate()
+         .map(|(i, len)| (0..*len as u8).map(|b| b^(i as u8)).collect()).collect();
+       let mut keys:Vec<AesKey> = vec![];
+       for backend in [Backend::AesNi, Backend::Bitsliced, Backend::Table, Backend::AesNi] {
+         keys.extend(raw.iter().map(|key| AesKey::with_backend(key,backend).unwrap()));
+       }
+       let refs:Vec<&AesKey> = keys.iter().collect();
+       let plain:Vec<[u8;16]> = (0..keys.len()).map(|i| [i as u8; 16]).collect();
+       let mut blocks = plain.clone();
+       encrypt_blocks_multi(&refs,&mut blocks);
+       for (i, block) in blocks.iter().enumerate() {
+         assert_eq!(*block,AesKey::new(&raw[i%raw.len()]).unwrap().encrypt_block(plain[i]));
+       }
+    }
+}
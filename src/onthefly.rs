@@ -0,0 +1,127 @@
+/*
+On-the-fly key schedule
+Only the cipher key is kept, the round keys are computed word by word in
+a window of Nk words (32 bytes at most) while a block is processed: word
+i replaces word i-Nk for encryption, and decryption first runs the
+schedule to its end and then backwards, w[i-Nk] = w[i] ^ f(w[i-1])
+*/
+use crate::{add_round_key, create_state, inv_mix_columns, inv_shift_rows, inv_sub_bytes, mix_columns,
+  rot_word, shift_rows, state2data_block, sub_bytes, sub_word};
+use crate::gf::RCON;
+// AES-128, AES-192 or AES-256 key without an expanded schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OnTheFlyKey {
+  key: [[u8;4];8],
+  nk: u8,
+}
+// Term added to w[i-Nk] to give word i of the schedule
+fn schedule_word(prev: [u8;4], i: usize, nk: usize) -> [u8;4] {
+  if i.is_multiple_of(nk) {
+    let mut t = sub_word(rot_word(prev));
+    t[0] ^= RCON[i/nk];
+    t
+  } else if nk > 6 && i%nk == 4 {
+    sub_word(prev)
+  } else {
+    prev
+  }
+}
+//
+fn xor_word(a: [u8;4], b: [u8;4]) -> [u8;4] {
+  [a[0]^b[0], a[1]^b[1], a[2]^b[2], a[3]^b[3]]
+}
+//
+impl OnTheFlyKey {
+  pub fn new(key: &[u8]) -> Result<OnTheFlyKey, &'static str> {
+    if key.len() != 16 && key.len() != 24 && key.len() != 32 {
+      return Err("key must be 128, 192 or 256 bits");
+    }
+    let mut words:[[u8;4];8] = [[0;4];8];
+    for (word, bytes) in words.iter_mut().zip(key.chunks_exact(4)) {
+      word.copy_from_slice(bytes);
+    }
+    Ok(OnTheFlyKey { key: words, nk: (key.len()/4) as u8 })
+  }
+  //
+  pub fn rounds(&self) -> usize {
+    self.nk as usize+6
+  }
+  // Word i of the window goes to slot i % Nk
+  pub fn encrypt_block(&self, block: [u8;16]) -> [u8;16] {
+    let nk = self.nk as usize;
+    let nr = nk+6;
+    let mut w = self.key;
+    let mut state:[[u8;4];4] = create_state(&block);
+    for round in 0..=nr {
+      let mut k:[[u8;4];4] = [[0;4];4];
+      for (c, column) in k.iter_mut().enumerate() {
+        let i = 4*round+c;
+        if i >= nk {
+          w[i%nk] = xor_word(w[i%nk],schedule_word(w[(i-1)%nk],i,nk));
+        }
+        *column = w[i%nk];
+      }
+      if round != 0 {
+        state = shift_rows(sub_bytes(state));
+        if round != nr {
+          state = mix_columns(state);
+        }
+      }
+      state = add_round_key(state,&k);
+    }
+    state2data_block(state)
+  }
+  // Straightforward inverse cipher with the schedule run backwards
+  pub fn decrypt_block(&self, block: [u8;16]) -> [u8;16] {
+    let nk = self.nk as usize;
+    let nr = nk+6;
+    let total = 4*(nr+1);
+    let mut w = self.key;
+    for i in nk..total {
+      w[i%nk] = xor_word(w[i%nk],schedule_word(w[(i-1)%nk],i,nk));
+    }
+    // the window holds words lowest..lowest+Nk
+    let mut lowest = total-nk;
+    let mut state:[[u8;4];4] = create_state(&block);
+    for round in (0..=nr).rev() {
+      while lowest > 4*round {
+        let i = lowest-1+nk;
+        w[i%nk] = xor_word(w[i%nk],schedule_word(w[(i-1)%nk],i,nk));
+        lowest -= 1;
+      }
+      let k:[[u8;4];4] = core::array::from_fn(|c| w[(4*round+c)%nk]);
+      if round != nr {
+        state = inv_sub_bytes(inv_shift_rows(state));
+      }
+      state = add_round_key(state,&k);
+      if round != nr && round != 0 {
+        state = inv_mix_columns(state);
+      }
+    }
+    state2data_block(state)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AesKey;
+    #[test]
+    fn test_on_the_fly() {
+       // FIPS-197 Appendix C
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       let expected:[[u8;16];3] = [
+         [0x69,0xc4,0xe0,0xd8,0x6a,0x7b,0x04,0x30,0xd8,0xcd,0xb7,0x80,0x70,0xb4,0xc5,0x5a],
+         [0xdd,0xa9,0x7c,0xa4,0x86,0x4c,0xdf,0xe0,0x6e,0xaf,0x70,0xa0,0xec,0x0d,0x71,0x91],
+         [0x8e,0xa2,0xb7,0xca,0x51,0x67,0x45,0xbf,0xea,0xfc,0x49,0x90,0x4b,0x49,0x60,0x89]];
+       for (len, cipher) in [16, 24, 32].into_iter().zip(expected) {
+         let key:Vec<u8> = (0..len as u8).collect();
+         let otf = OnTheFlyKey::new(&key).unwrap();
+         assert_eq!(otf.rounds(),AesKey::new(&key).unwrap().rounds);
+         assert_eq!(otf.encrypt_block(plain),cipher);
+         assert_eq!(otf.decrypt_block(cipher),plain);
+       }
+       assert!(std::mem::size_of::<OnTheFlyKey>() <= 33);
+       assert!(OnTheFlyKey::new(&[0;20]).is_err());
+    }
+}
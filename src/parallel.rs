@@ -0,0 +1,51 @@
+/*
+Worker pool for the parallelisable modes
+The data is split into chunks that scoped std::thread workers take in
+order from a shared iterator, every chunk is processed independently so
+the result does not depend on the number of threads
+*/
+use std::sync::Mutex;
+use std::thread;
+// Chunk size of the modes, a whole number of blocks
+pub const CHUNK: usize = 1 << 16;
+// Runs job(index, chunk) for every chunk_len bytes of data on up to
+// threads workers, one thread runs everything in the caller
+pub fn for_each_chunk<F>(threads: usize, data: &mut [u8], chunk_len: usize, job: F)
+where F: Fn(usize, &mut [u8]) + Sync {
+  let workers = threads.min(data.len().div_ceil(chunk_len));
+  let chunks = data.chunks_mut(chunk_len).enumerate();
+  if workers <= 1 {
+    chunks.for_each(|(index, chunk)| job(index,chunk));
+    return;
+  }
+  let work = Mutex::new(chunks);
+  thread::scope(|scope| {
+    for _ in 0..workers {
+      scope.spawn(|| loop {
+        let next = work.lock().unwrap().next();
+        match next {
+          Some((index, chunk)) => job(index,chunk),
+          None => break,
+        }
+      });
+    }
+  });
+}
+// Number of threads the host can run at once
+pub fn available_threads() -> usize {
+  thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_for_each_chunk() {
+       for threads in [1, 3, 8] {
+         let mut data:Vec<u8> = vec![0; 1000];
+         for_each_chunk(threads,&mut data,64,|index, chunk| chunk.fill(index as u8));
+         let expected:Vec<u8> = (0..1000).map(|i| (i/64) as u8).collect();
+         assert_eq!(data,expected);
+       }
+    }
+}
@@ -0,0 +1,96 @@
+/*
+Reduced-round AES for cryptanalysis experiments
+NOT a secure cipher, use AesKey or encrypt_block for real data
+*/
+use crate::{add_round_key, create_state, expand_key, inv_mix_columns, inv_shift_rows,
+            inv_sub_bytes, mix_columns, shift_rows, state2data_block, sub_bytes};
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReducedRoundAes {
+    pub rounds: usize,
+    pub final_mix_columns: bool,
+    pub keys: [[[u8; 4] ;4]; 15],
+}
+//
+impl ReducedRoundAes {
+    // rounds goes from 1 to the full Nr of the key size, the last round
+    // keeps MixColumns when final_mix_columns is set
+    pub fn new_insecure(key: &[u8], rounds: usize, final_mix_columns: bool) -> Result<ReducedRoundAes, &'static str> {
+        if key.len() != 16 && key.len() != 24 && key.len() != 32 {
+            return Err("key must be 128, 192 or 256 bits");
+        }
+        if rounds == 0 || rounds > key.len()/4+6 {
+            return Err("rounds must be between 1 and Nr for the key size");
+        }
+        Ok(ReducedRoundAes { rounds, final_mix_columns, keys: expand_key(key,false) })
+    }
+    pub fn encrypt_block(&self, block: [u8;16]) -> [u8;16] {
+        let mut state = create_state(&block);
+        state = add_round_key(state,&self.keys[0]);
+        for i in 1..self.rounds {
+            state = sub_bytes(state);
+            state = shift_rows(state);
+            state = mix_columns(state);
+            state = add_round_key(state,&self.keys[i]);
+        }
+        state = sub_bytes(state);
+        state = shift_rows(state);
+        if self.final_mix_columns {
+            state = mix_columns(state);
+        }
+        state = add_round_key(state,&self.keys[self.rounds]);
+        state2data_block(state)
+    }
+    // Straightforward inverse cipher with the encryption round keys
+    pub fn decrypt_block(&self, block: [u8;16]) -> [u8;16] {
+        let mut state = create_state(&block);
+        state = add_round_key(state,&self.keys[self.rounds]);
+        if self.final_mix_columns {
+            state = inv_mix_columns(state);
+        }
+        state = inv_shift_rows(state);
+        state = inv_sub_bytes(state);
+        for i in (1..self.rounds).rev() {
+            state = add_round_key(state,&self.keys[i]);
+            state = inv_mix_columns(state);
+            state = inv_shift_rows(state);
+            state = inv_sub_bytes(state);
+        }
+        state = add_round_key(state,&self.keys[0]);
+        state2data_block(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AesKey;
+    #[test]
+    fn test_full_rounds_match_aes() {
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       for len in [16,24,32] {
+         let key:Vec<u8> = (0..len as u8).collect();
+         let aes = AesKey::new(&key).unwrap();
+         let full = ReducedRoundAes::new_insecure(&key,aes.rounds,false).unwrap();
+         assert_eq!(full.encrypt_block(plain),aes.encrypt_block(plain));
+       }
+    }
+    #[test]
+    fn test_reduced_rounds() {
+       let key:[u8;16]=[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       for rounds in 1..=10 {
+         for final_mix_columns in [false, true] {
+           let aes = ReducedRoundAes::new_insecure(&key,rounds,final_mix_columns).unwrap();
+           let cipher = aes.encrypt_block(plain);
+           assert_eq!(aes.decrypt_block(cipher),plain);
+         }
+       }
+       // FIPS-197 Appendix C.1 round[ 5].start, 4 rounds with MixColumns
+       let four = ReducedRoundAes::new_insecure(&key,4,true).unwrap();
+       let expected:[u8;16]=[0x24,0x72,0x40,0x23,0x69,0x66,0xb3,0xfa,0x6e,0xd2,0x75,0x32,0x88,0x42,0x5b,0x6c];
+       assert_eq!(four.encrypt_block(plain),expected);
+       assert!(ReducedRoundAes::new_insecure(&key,11,false).is_err());
+       assert!(ReducedRoundAes::new_insecure(&key,0,false).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+/*
+Rijndael with 128, 192 and 256 bit blocks (Nb = 4, 6 or 8 columns)
+and 128, 192 or 256 bit keys
+AES is the Nb = 4 member of the family
+*/
+use crate::{cipher, cipher_with, inv_cipher, inv_cipher_with, expand_key, expand_key_with};
+use crate::sbox::inverse_sbox;
+use crate::trace::NoTrace;
+// Blocks are 4*Nb bytes
+fn check_block<const NB: usize>(block: &[u8]) -> Result<(), &'static str> {
+    if block.len() != 4*NB {
+        return Err("block length does not match the Rijndael block size");
+    }
+    Ok(())
+}
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RijndaelKey<const NB: usize> {
+    pub rounds: usize,
+    pub enc: [[[u8; 4] ;NB]; 15],
+    pub dec: [[[u8; 4] ;NB]; 15],
+}
+//
+pub type Rijndael128 = RijndaelKey<4>;
+pub type Rijndael192 = RijndaelKey<6>;
+pub type Rijndael256 = RijndaelKey<8>;
+//
+impl<const NB: usize> RijndaelKey<NB> {
+    pub fn new(key: &[u8]) -> Result<RijndaelKey<NB>, &'static str> {
+        if NB != 4 && NB != 6 && NB != 8 {
+            return Err("block must be 128, 192 or 256 bits");
+        }
+        if key.len() != 16 && key.len() != 24 && key.len() != 32 {
+            return Err("key must be 128, 192 or 256 bits");
+        }
+        let rounds = (key.len()/4).max(NB)+6;
+        Ok(RijndaelKey { rounds, enc: expand_key(key,false), dec: expand_key(key,true) })
+    }
+    // Block length in bytes
+    pub fn block_len(&self) -> usize {
+        4*NB
+    }
+    pub fn encrypt_block(&self, block: &[u8]) -> Result<Vec<u8>, &'static str> {
+        check_block::<NB>(block)?;
+        Ok(cipher(block,&self.enc,self.rounds,&mut NoTrace).concat())
+    }
+    pub fn decrypt_block(&self, block: &[u8]) -> Result<Vec<u8>, &'static str> {
+        check_block::<NB>(block)?;
+        Ok(inv_cipher(block,&self.dec,self.rounds,&mut NoTrace).concat())
+    }
+}
+// Rijndael with a user supplied S-box for research on AES-like ciphers,
+// the inverse S-box is derived and the key schedule uses the same S-box
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CustomRijndael<const NB: usize> {
+    pub rounds: usize,
+    pub sbox: [u8;256],
+    pub inv_sbox: [u8;256],
+    pub enc: [[[u8; 4] ;NB]; 15],
+    pub dec: [[[u8; 4] ;NB]; 15],
+}
+//
+impl<const NB: usize> CustomRijndael<NB> {
+    pub fn new(key: &[u8], sbox: [u8;256]) -> Result<CustomRijndael<NB>, &'static str> {
+        let standard = RijndaelKey::<NB>::new(key)?;
+        let inv_sbox = inverse_sbox(&sbox)?;
+        Ok(CustomRijndael { rounds: standard.rounds, sbox, inv_sbox,
+            enc: expand_key_with(key,false,&sbox), dec: expand_key_with(key,true,&sbox) })
+    }
+    pub fn encrypt_block(&self, block: &[u8]) -> Result<Vec<u8>, &'static str> {
+        check_block::<NB>(block)?;
+        Ok(cipher_with(block,&self.enc,self.rounds,&self.sbox,&mut NoTrace).concat())
+    }
+    pub fn decrypt_block(&self, block: &[u8]) -> Result<Vec<u8>, &'static str> {
+        check_block::<NB>(block)?;
+        Ok(inv_cipher_with(block,&self.dec,self.rounds,&self.inv_sbox,&mut NoTrace).concat())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+    const KEY:[u8;32]=[0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c,0x76,0x2e,0x71,0x60,0xf3,0x8b,0x4d,0xa5,0x6a,0x78,0x4d,0x90,0x45,0x19,0x0c,0xfe];
+    const PLAIN:[u8;32]=[0x32,0x43,0xf6,0xa8,0x88,0x5a,0x30,0x8d,0x31,0x31,0x98,0xa2,0xe0,0x37,0x07,0x34,0x4a,0x40,0x93,0x82,0x22,0x99,0xf3,0x1d,0x00,0x82,0xef,0xa9,0x8e,0xc4,0xe6,0xc8];
+    #[test]
+    fn test_rijndael_128() {
+       // Rijndael reference vectors, block = key bytes 2b7e1516... / 3243f6a8...
+       let expected = ["3925841d02dc09fbdc118597196a0b32","f9fb29aefc384a250340d833b87ebc00","1a6e6c2c662e7da6501ffb62bc9e93f3"];
+       for (x, k) in [16,24,32].iter().enumerate() {
+         let rijndael = Rijndael128::new(&KEY[..*k]).unwrap();
+         let cipher = rijndael.encrypt_block(&PLAIN[..16]).unwrap();
+         assert_eq!(hex(&cipher),expected[x]);
+         assert_eq!(rijndael.decrypt_block(&cipher).unwrap(),PLAIN[..16]);
+       }
+    }
+    #[test]
+    fn test_rijndael_192() {
+       let expected = ["b24d275489e82bb8f7375e0d5fcdb1f481757c538b65148a","725ae43b5f3161de806a7c93e0bca93c967ec1ae1b71e1cf","0ebacf199e3315c2e34b24fcc7c46ef4388aa475d66c194c"];
+       for (x, k) in [16,24,32].iter().enumerate() {
+         let rijndael = Rijndael192::new(&KEY[..*k]).unwrap();
+         assert_eq!(rijndael.rounds,(k/4).max(6)+6);
+         let cipher = rijndael.encrypt_block(&PLAIN[..24]).unwrap();
+         assert_eq!(hex(&cipher),expected[x]);
+         assert_eq!(rijndael.decrypt_block(&cipher).unwrap(),PLAIN[..24]);
+       }
+    }
+    #[test]
+    fn test_rijndael_256() {
+       let expected = ["7d15479076b69a46ffb3b3beae97ad8313f622f67fedb487de9f06b9ed9c8f19","5d7101727bb25781bf6715b0e6955282b9610e23a43c2eb062699f0ebf5887b2","a49406115dfb30a40418aafa4869b7c6a886ff31602a7dd19c889dc64f7e4e7a"];
+       for (x, k) in [16,24,32].iter().enumerate() {
+         let rijndael = Rijndael256::new(&KEY[..*k]).unwrap();
+         assert_eq!(rijndael.rounds,14);
+         let cipher = rijndael.encrypt_block(&PLAIN).unwrap();
+         assert_eq!(hex(&cipher),expected[x]);
+         assert_eq!(rijndael.decrypt_block(&cipher).unwrap(),PLAIN);
+       }
+       assert!(RijndaelKey::<5>::new(&KEY).is_err());
+       let rijndael = Rijndael256::new(&KEY).unwrap();
+       assert!(rijndael.encrypt_block(&PLAIN[..16]).is_err());
+       assert!(rijndael.decrypt_block(&[]).is_err());
+    }
+    #[test]
+    fn test_custom_sbox() {
+       use crate::gf::{affine, inverse, SBOX};
+       for len in [16,24,32] {
+         let standard = Rijndael192::new(&KEY[..len]).unwrap();
+         let custom = CustomRijndael::<6>::new(&KEY[..len],SBOX).unwrap();
+         assert_eq!(custom.encrypt_block(&PLAIN[..24]).unwrap(),standard.encrypt_block(&PLAIN[..24]).unwrap());
+       }
+       // affine constant 0x00 instead of 0x63
+       let sbox:[u8;256] = core::array::from_fn(|x| affine(inverse(x as u8),0x00));
+       let custom = CustomRijndael::<4>::new(&KEY[..16],sbox).unwrap();
+       let cipher = custom.encrypt_block(&PLAIN[..16]).unwrap();
+       assert_ne!(hex(&cipher),"3925841d02dc09fbdc118597196a0b32");
+       assert_eq!(custom.decrypt_block(&cipher).unwrap(),PLAIN[..16]);
+       assert_eq!(custom.inv_sbox[sbox[0x53] as usize],0x53);
+       let mut not_bijective = sbox;
+       not_bijective[0] = not_bijective[1];
+       assert!(CustomRijndael::<4>::new(&KEY[..16],not_bijective).is_err());
+       assert!(CustomRijndael::<4>::new(&KEY[..20],sbox).is_err());
+       assert!(custom.encrypt_block(&PLAIN[..24]).is_err());
+    }
+}
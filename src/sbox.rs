@@ -0,0 +1,182 @@
+/*
+S-box analysis
+Difference distribution table, linear approximation table and the
+properties derived from them, for the AES S-box or any 8-bit S-box
+*/
+use std::fmt::Display;
+use crate::gf::{INV_SBOX, SBOX};
+//
+pub fn aes_sbox() -> [u8;256] {
+  SBOX
+}
+//
+pub fn aes_inv_sbox() -> [u8;256] {
+  INV_SBOX
+}
+// 256 hex bytes separated by spaces, commas or new lines, 0x is optional
+pub fn parse_sbox(text: &str) -> Result<[u8;256], &'static str> {
+  let bytes:Vec<u8> = text.split(|c: char| c.is_whitespace() || c == ',')
+    .filter(|word| !word.is_empty())
+    .map(|word| u8::from_str_radix(word.trim_start_matches("0x"),16).map_err(|_| "invalid hex byte"))
+    .collect::<Result<_, _>>()?;
+  bytes.try_into().map_err(|_| "an S-box has 256 entries")
+}
+//
+pub fn inverse_sbox(sbox: &[u8;256]) -> Result<[u8;256], &'static str> {
+  let mut inverse:[u8;256] = [0;256];
+  let mut seen:[bool;256] = [false;256];
+  for (x, y) in sbox.iter().enumerate() {
+    if seen[*y as usize] {
+      return Err("S-box is not a bijection");
+    }
+    seen[*y as usize] = true;
+    inverse[*y as usize] = x as u8;
+  }
+  Ok(inverse)
+}
+// DDT[a][b] = #{x : S(x) ^ S(x ^ a) = b}
+pub fn ddt(sbox: &[u8;256]) -> Vec<[u16;256]> {
+  let mut table = vec![[0u16;256];256];
+  for (a, row) in table.iter_mut().enumerate() {
+    for x in 0..256 {
+      row[(sbox[x]^sbox[x^a]) as usize] += 1;
+    }
+  }
+  table
+}
+// LAT[a][b] = #{x : a.x = b.S(x)} - 128, one Walsh-Hadamard transform per b
+pub fn lat(sbox: &[u8;256]) -> Vec<[i16;256]> {
+  let mut table = vec![[0i16;256];256];
+  for b in 0..256 {
+    let mut walsh:[i16;256] = [0;256];
+    for (x, w) in walsh.iter_mut().enumerate() {
+      *w = if (sbox[x] as usize & b).count_ones().is_multiple_of(2) { 1 } else { -1 };
+    }
+    let mut h = 1;
+    while h < 256 {
+      for i in (0..256).step_by(2*h) {
+        for j in i..i+h {
+          let (u, v) = (walsh[j], walsh[j+h]);
+          walsh[j] = u+v;
+          walsh[j+h] = u-v;
+        }
+      }
+      h *= 2;
+    }
+    for (row, w) in table.iter_mut().zip(walsh) {
+      row[b] = w/2;
+    }
+  }
+  table
+}
+// Largest DDT entry for a non-zero input difference
+pub fn differential_uniformity(ddt: &[[u16;256]]) -> u16 {
+  ddt[1..].iter().flat_map(|row| row.iter()).copied().max().unwrap_or(0)
+}
+// 128 - largest |LAT| entry for a non-zero output mask
+pub fn nonlinearity(lat: &[[i16;256]]) -> u16 {
+  128 - lat.iter().flat_map(|row| row[1..].iter()).map(|v| v.unsigned_abs()).max().unwrap_or(0)
+}
+//
+pub fn fixed_points(sbox: &[u8;256]) -> Vec<u8> {
+  (0..=255u8).filter(|x| sbox[*x as usize] == *x).collect()
+}
+// S(x) = !x
+pub fn opposite_fixed_points(sbox: &[u8;256]) -> Vec<u8> {
+  (0..=255u8).filter(|x| sbox[*x as usize] == !*x).collect()
+}
+// Highest degree of the algebraic normal form of the output bits
+pub fn algebraic_degree(sbox: &[u8;256]) -> u32 {
+  let mut degree = 0;
+  for bit in 0..8 {
+    let mut anf:[u8;256] = [0;256];
+    for (x, coefficient) in anf.iter_mut().enumerate() {
+      *coefficient = (sbox[x] >> bit) & 1;
+    }
+    // Moebius transform
+    for i in 0..8 {
+      for x in 0..256 {
+        if x & (1 << i) != 0 {
+          anf[x] ^= anf[x ^ (1 << i)];
+        }
+      }
+    }
+    for (x, coefficient) in anf.iter().enumerate() {
+      if *coefficient == 1 {
+        degree = degree.max(x.count_ones());
+      }
+    }
+  }
+  degree
+}
+// One row per input difference or mask, one column per output
+pub fn table_csv<T: Display>(table: &[[T;256]]) -> String {
+  let mut csv = String::from("in\\out");
+  for b in 0..256 {
+    csv.push_str(&format!(",{b}"));
+  }
+  csv.push('\n');
+  for (a, row) in table.iter().enumerate() {
+    csv.push_str(&a.to_string());
+    for value in row.iter() {
+      csv.push_str(&format!(",{value}"));
+    }
+    csv.push('\n');
+  }
+  csv
+}
+//
+pub fn properties_csv(sbox: &[u8;256]) -> String {
+  let hex = |points: Vec<u8>| points.iter().map(|x| format!("{x:02x}")).collect::<Vec<_>>().join(" ");
+  let mut csv = String::from("property,value\n");
+  csv.push_str(&format!("bijective,{}\n",inverse_sbox(sbox).is_ok()));
+  csv.push_str(&format!("differential uniformity,{}\n",differential_uniformity(&ddt(sbox))));
+  csv.push_str(&format!("nonlinearity,{}\n",nonlinearity(&lat(sbox))));
+  csv.push_str(&format!("algebraic degree,{}\n",algebraic_degree(sbox)));
+  csv.push_str(&format!("fixed points,{}\n",hex(fixed_points(sbox))));
+  csv.push_str(&format!("opposite fixed points,{}\n",hex(opposite_fixed_points(sbox))));
+  csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_aes_sbox_properties() {
+       let sbox = aes_sbox();
+       assert_eq!(sbox[0x53],0xed);
+       assert_eq!(inverse_sbox(&sbox).unwrap(),aes_inv_sbox());
+       let ddt = ddt(&sbox);
+       assert!(ddt.iter().all(|row| row.iter().sum::<u16>() == 256));
+       assert_eq!(ddt[0][0],256);
+       assert_eq!(differential_uniformity(&ddt),4);
+       let lat = lat(&sbox);
+       assert_eq!(lat[0][0],128);
+       assert_eq!(nonlinearity(&lat),112);
+       assert_eq!(algebraic_degree(&sbox),7);
+       assert!(fixed_points(&sbox).is_empty());
+       assert!(opposite_fixed_points(&sbox).is_empty());
+    }
+    #[test]
+    fn test_other_sboxes() {
+       let identity:[u8;256] = core::array::from_fn(|x| x as u8);
+       assert_eq!(differential_uniformity(&ddt(&identity)),256);
+       assert_eq!(nonlinearity(&lat(&identity)),0);
+       assert_eq!(algebraic_degree(&identity),1);
+       assert_eq!(fixed_points(&identity).len(),256);
+       let mut constant:[u8;256] = [7;256];
+       assert!(inverse_sbox(&constant).is_err());
+       constant[0] = 1;
+       assert!(properties_csv(&constant).contains("bijective,false\n"));
+    }
+    #[test]
+    fn test_parse_sbox() {
+       let text:String = aes_sbox().iter().map(|x| format!("0x{x:02X}, ")).collect();
+       assert_eq!(parse_sbox(&text).unwrap(),aes_sbox());
+       assert!(parse_sbox("63 7c 77").is_err());
+       assert!(parse_sbox("zz").is_err());
+       let csv = table_csv(&ddt(&aes_sbox()));
+       assert_eq!(csv.lines().count(),257);
+       assert!(csv.starts_with("in\\out,0,1,2,"));
+    }
+}
@@ -0,0 +1,74 @@
+/*
+Square (integral) attack on 4-round AES-128
+Daemen, Knudsen, Rijmen, "The block cipher Square", FSE 1997
+After three rounds every byte of a Λ-set is balanced, so one guess of a
+last round key byte is checked by XORing the partially decrypted byte
+over the 256 ciphertexts of the set
+*/
+use crate::gf::INV_SBOX;
+use crate::invert_key_expansion;
+// 256 plaintexts, byte active takes every value and the rest are constant
+pub fn lambda_set(active: usize, constant: [u8;16]) -> Vec<[u8;16]> {
+  (0..=255u8).map(|value| {
+    let mut block = constant;
+    block[active] = value;
+    block
+  }).collect()
+}
+// Candidates for byte j of the last round key that leave byte j balanced
+fn balanced_guesses(ciphertexts: &[[u8;16]], j: usize) -> Vec<u8> {
+  (0..=255u8).filter(|guess| {
+    ciphertexts.iter().fold(0, |sum, c| sum ^ INV_SBOX[(c[j]^guess) as usize]) == 0
+  }).collect()
+}
+// Queries Λ-sets until each key byte has a single candidate, oracle is a
+// 4-round encryption without MixColumns in the last round
+pub fn recover_last_round_key<F: FnMut([u8;16]) -> [u8;16]>(mut oracle: F, max_sets: usize) -> Option<[u8;16]> {
+  let mut candidates:Vec<Vec<u8>> = vec![(0..=255u8).collect(); 16];
+  for set in 0..max_sets {
+    let constant:[u8;16] = [set as u8;16];
+    let ciphertexts:Vec<[u8;16]> = lambda_set(0,constant).into_iter().map(&mut oracle).collect();
+    for (j, bytes) in candidates.iter_mut().enumerate() {
+      let guesses = balanced_guesses(&ciphertexts,j);
+      bytes.retain(|b| guesses.contains(b));
+    }
+    if candidates.iter().all(|bytes| bytes.len() == 1) {
+      let mut key:[u8;16] = [0;16];
+      for (j, bytes) in candidates.iter().enumerate() {
+        key[j] = bytes[0];
+      }
+      return Some(key);
+    }
+  }
+  None
+}
+// Last round key and the AES-128 key found by inverting the key schedule
+pub fn square_attack<F: FnMut([u8;16]) -> [u8;16]>(oracle: F, max_sets: usize) -> Option<([u8;16], Vec<u8>)> {
+  let round_key = recover_last_round_key(oracle,max_sets)?;
+  let (key, _) = invert_key_expansion(16,4,&round_key).ok()?;
+  Some((round_key, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::research::ReducedRoundAes;
+    #[test]
+    fn test_balanced_after_three_rounds() {
+       let key:[u8;16]=[0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c];
+       let three = ReducedRoundAes::new_insecure(&key,3,true).unwrap();
+       let sum = lambda_set(5,[0x42;16]).into_iter().map(|p| three.encrypt_block(p))
+           .fold([0u8;16], |mut sum, c| { sum.iter_mut().zip(c).for_each(|(s, b)| *s ^= b); sum });
+       assert_eq!(sum,[0;16]);
+    }
+    #[test]
+    fn test_square_attack() {
+       let key:[u8;16]=[0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c];
+       let four = ReducedRoundAes::new_insecure(&key,4,false).unwrap();
+       let mut queries = 0;
+       let (round_key, recovered) = square_attack(|p| { queries += 1; four.encrypt_block(p) },8).unwrap();
+       assert_eq!(round_key,four.keys[4].concat()[..]);
+       assert_eq!(recovered,key);
+       assert!(queries <= 8*256);
+    }
+}
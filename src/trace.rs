@@ -0,0 +1,257 @@
+/*
+Round by round tracing of the cipher
+An Observer is called after every transformation with the round number
+and the state (one [u8;4] per column)
+*/
+#[cfg(feature = "std")]
+use std::fmt::Write;
+#[cfg(feature = "std")]
+use crate::AesKey;
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Input,
+    SubBytes,
+    ShiftRows,
+    MixColumns,
+    AddRoundKey,
+    InvSubBytes,
+    InvShiftRows,
+    InvMixColumns,
+    Output,
+}
+//
+impl Step {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::Input => "input",
+            Step::SubBytes => "sub_bytes",
+            Step::ShiftRows => "shift_rows",
+            Step::MixColumns => "mix_columns",
+            Step::AddRoundKey => "add_round_key",
+            Step::InvSubBytes => "inv_sub_bytes",
+            Step::InvShiftRows => "inv_shift_rows",
+            Step::InvMixColumns => "inv_mix_columns",
+            Step::Output => "output",
+        }
+    }
+}
+//
+pub trait Observer {
+    fn observe(&mut self, _round: usize, _step: Step, _state: &[[u8; 4]]) {}
+    // called with the round key just before it is added to the state
+    fn round_key(&mut self, _round: usize, _key: &[[u8; 4]]) {}
+}
+// Default observer, every call compiles to nothing
+pub struct NoTrace;
+//
+impl Observer for NoTrace {}
+//
+#[cfg(feature = "std")]
+const WIDTH:usize = 18;
+// FIPS-197 Appendix B style table, one row of state matrices per round
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct RoundTable {
+    pub output: String,
+    body: String,
+    row: [Option<Vec<[u8; 4]>>; 5],
+    started: bool,
+    decrypt: bool,
+}
+//
+#[cfg(feature = "std")]
+impl RoundTable {
+    pub fn new() -> RoundTable {
+        RoundTable::default()
+    }
+    fn flush(&mut self, label: &str) {
+        for r in 0..4 {
+            let name = if r==1 { label } else { "" };
+            let mut line = format!("{name:>6}  ");
+            for slot in self.row.iter() {
+                let cell = match slot {
+                    Some(state) => state.iter().map(|column| format!("{:02x}",column[r])).collect::<Vec<_>>().join(" "),
+                    None => String::new(),
+                };
+                line.push_str(&format!("{cell:<WIDTH$}"));
+            }
+            writeln!(self.body,"{}",line.trim_end()).unwrap();
+        }
+        writeln!(self.body).unwrap();
+        self.row = Default::default();
+    }
+    fn header(&self) -> String {
+        let names = if self.decrypt {
+            ["Start of Round", "After InvSubBytes", "After InvShiftRows", "After InvMixColumns", "Round Key Value"]
+        } else {
+            ["Start of Round", "After SubBytes", "After ShiftRows", "After MixColumns", "Round Key Value"]
+        };
+        let mut line = format!("{:>6}  ","Round");
+        for name in names {
+            line.push_str(&format!("{name:<WIDTH$}"));
+        }
+        format!("{}\n\n",line.trim_end())
+    }
+}
+//
+#[cfg(feature = "std")]
+impl Observer for RoundTable {
+    fn observe(&mut self, round: usize, step: Step, state: &[[u8; 4]]) {
+        let slot = match step {
+            Step::Input => 0,
+            Step::SubBytes | Step::InvSubBytes => 1,
+            Step::ShiftRows | Step::InvShiftRows => 2,
+            Step::MixColumns | Step::InvMixColumns => 3,
+            Step::AddRoundKey => {
+                let label = if self.started { round.to_string() } else { "input".to_string() };
+                self.flush(&label);
+                self.started = true;
+                0
+            },
+            Step::Output => {
+                self.row = Default::default();
+                self.row[0] = Some(state.to_vec());
+                self.flush("output");
+                let header = self.header();
+                self.output.push_str(&header);
+                self.output.push_str(&self.body);
+                self.body.clear();
+                self.started = false;
+                self.decrypt = false;
+                return;
+            },
+        };
+        if matches!(step, Step::InvSubBytes | Step::InvShiftRows | Step::InvMixColumns) {
+            self.decrypt = true;
+        }
+        self.row[slot] = Some(state.to_vec());
+    }
+    fn round_key(&mut self, _round: usize, key: &[[u8; 4]]) {
+        self.row[4] = Some(key.to_vec());
+    }
+}
+//
+#[cfg(feature = "std")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+// State bytes in column order, the same order as the input block
+#[cfg(feature = "std")]
+fn state_hex(state: &[[u8; 4]]) -> String {
+    hex(state.as_flattened())
+}
+// Every intermediate state and round key of a block, for json_trace
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct JsonTrace {
+    pub steps: Vec<(usize, Step, String)>,
+    pub round_keys: Vec<(usize, String)>,
+}
+//
+#[cfg(feature = "std")]
+impl Observer for JsonTrace {
+    fn observe(&mut self, round: usize, step: Step, state: &[[u8; 4]]) {
+        self.steps.push((round, step, state_hex(state)));
+    }
+    fn round_key(&mut self, round: usize, key: &[[u8; 4]]) {
+        self.round_keys.push((round, state_hex(key)));
+    }
+}
+// JSON document with the key schedule and every state of one block,
+// states are hex strings in column order as in FIPS-197 Appendix C
+#[cfg(feature = "std")]
+pub fn json_trace(key: &[u8], block: [u8;16], decrypt: bool) -> Result<String, &'static str> {
+    let aes = AesKey::new(key)?;
+    let mut trace = JsonTrace::default();
+    let output = if decrypt {
+        aes.decrypt_block_with(block,&mut trace)
+    } else {
+        aes.encrypt_block_with(block,&mut trace)
+    };
+    let mut json = String::from("{\n");
+    writeln!(json,"  \"cipher\": \"AES-{}\",",key.len()*8).unwrap();
+    writeln!(json,"  \"direction\": \"{}\",",if decrypt { "decrypt" } else { "encrypt" }).unwrap();
+    writeln!(json,"  \"rounds\": {},",aes.rounds).unwrap();
+    writeln!(json,"  \"key\": \"{}\",",hex(key)).unwrap();
+    writeln!(json,"  \"input\": \"{}\",",hex(&block)).unwrap();
+    writeln!(json,"  \"output\": \"{}\",",hex(&output)).unwrap();
+    json.push_str("  \"key_schedule\": [\n");
+    let schedule:Vec<String> = aes.enc[..=aes.rounds].iter().map(|k| format!("    \"{}\"",state_hex(k))).collect();
+    json.push_str(&schedule.join(",\n"));
+    json.push_str("\n  ],\n  \"round_keys\": [\n");
+    let keys:Vec<String> = trace.round_keys.iter()
+        .map(|(round, key)| format!("    {{ \"round\": {round}, \"key\": \"{key}\" }}"))
+        .collect();
+    json.push_str(&keys.join(",\n"));
+    json.push_str("\n  ],\n  \"steps\": [\n");
+    let steps:Vec<String> = trace.steps.iter()
+        .map(|(round, step, state)| format!("    {{ \"round\": {round}, \"step\": \"{}\", \"state\": \"{state}\" }}",step.name()))
+        .collect();
+    json.push_str(&steps.join(",\n"));
+    json.push_str("\n  ]\n}\n");
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // FIPS-197 Appendix B
+    const KEY:[u8;16]=[0x2b,0x7e,0x15,0x16,0x28,0xae,0xd2,0xa6,0xab,0xf7,0x15,0x88,0x09,0xcf,0x4f,0x3c];
+    const INPUT:[u8;16]=[0x32,0x43,0xf6,0xa8,0x88,0x5a,0x30,0x8d,0x31,0x31,0x98,0xa2,0xe0,0x37,0x07,0x34];
+    struct Steps(Vec<(usize, Step, Vec<[u8; 4]>)>);
+    impl Observer for Steps {
+        fn observe(&mut self, round: usize, step: Step, state: &[[u8; 4]]) {
+            self.0.push((round, step, state.to_vec()));
+        }
+    }
+    #[test]
+    fn test_observer_steps() {
+       let aes = AesKey::new(&KEY).unwrap();
+       let mut steps = Steps(vec![]);
+       let cipher = aes.encrypt_block_with(INPUT,&mut steps);
+       assert_eq!(cipher,aes.encrypt_block(INPUT));
+       // input, 9 full rounds of 4 steps, last round of 3 steps, output
+       assert_eq!(steps.0.len(),2+9*4+3+1);
+       assert_eq!(steps.0[1],(0,Step::AddRoundKey,vec![[0x19,0x3d,0xe3,0xbe],[0xa0,0xf4,0xe2,0x2b],[0x9a,0xc6,0x8d,0x2a],[0xe9,0xf8,0x48,0x08]]));
+       assert_eq!(steps.0[2],(1,Step::SubBytes,vec![[0xd4,0x27,0x11,0xae],[0xe0,0xbf,0x98,0xf1],[0xb8,0xb4,0x5d,0xe5],[0x1e,0x41,0x52,0x30]]));
+       assert_eq!(steps.0[4],(1,Step::MixColumns,vec![[0x04,0x66,0x81,0xe5],[0xe0,0xcb,0x19,0x9a],[0x48,0xf8,0xd3,0x7a],[0x28,0x06,0x26,0x4c]]));
+       let mut steps = Steps(vec![]);
+       assert_eq!(aes.decrypt_block_with(cipher,&mut steps),INPUT);
+       assert_eq!(steps.0[2].1,Step::InvSubBytes);
+       assert_eq!(steps.0.last().unwrap().1,Step::Output);
+    }
+    #[test]
+    fn test_round_table() {
+       let aes = AesKey::new(&KEY).unwrap();
+       let mut table = RoundTable::new();
+       aes.encrypt_block_with(INPUT,&mut table);
+       let lines:Vec<&str> = table.output.lines().collect();
+       assert!(lines[0].starts_with(" Round  Start of Round"));
+       // the input row only has the start of round and round key columns
+       assert_eq!(lines[2].split_whitespace().collect::<Vec<_>>(),["32","88","31","e0","2b","28","ab","09"]);
+       assert_eq!(lines[2].find("2b"),Some(8+4*WIDTH));
+       assert_eq!(lines[3].split_whitespace().collect::<Vec<_>>(),["input","43","5a","31","37","7e","ae","f7","cf"]);
+       assert_eq!(lines[7],"        19 a0 9a e9       d4 e0 b8 1e       d4 e0 b8 1e       04 e0 48 28       a0 88 23 2a");
+       assert_eq!(lines[8],"     1  3d f4 c6 f8       27 bf b4 41       bf b4 41 27       66 cb f8 06       fa 54 a3 6c");
+       assert_eq!(lines[lines.len()-4],"output  25 dc 11 6a");
+    }
+    #[test]
+    fn test_json_trace() {
+       // FIPS-197 Appendix C.1
+       let key:[u8;16]=[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       let json = json_trace(&key,plain,false).unwrap();
+       assert!(json.contains("\"cipher\": \"AES-128\","));
+       assert!(json.contains("\"output\": \"69c4e0d86a7b0430d8cdb78070b4c55a\","));
+       assert!(json.contains("{ \"round\": 0, \"step\": \"add_round_key\", \"state\": \"00102030405060708090a0b0c0d0e0f0\" }"));
+       assert!(json.contains("{ \"round\": 1, \"step\": \"sub_bytes\", \"state\": \"63cab7040953d051cd60e0e7ba70e18c\" }"));
+       assert!(json.contains("{ \"round\": 1, \"step\": \"shift_rows\", \"state\": \"6353e08c0960e104cd70b751bacad0e7\" }"));
+       assert!(json.contains("{ \"round\": 1, \"step\": \"mix_columns\", \"state\": \"5f72641557f5bc92f7be3b291db9f91a\" }"));
+       assert!(json.contains("{ \"round\": 1, \"key\": \"d6aa74fdd2af72fadaa678f1d6ab76fe\" }"));
+       assert_eq!(json.matches("\"step\"").count(),2+9*4+3+1);
+       let json = json_trace(&key,[0x69,0xc4,0xe0,0xd8,0x6a,0x7b,0x04,0x30,0xd8,0xcd,0xb7,0x80,0x70,0xb4,0xc5,0x5a],true).unwrap();
+       assert!(json.contains("\"output\": \"00112233445566778899aabbccddeeff\","));
+       assert!(json_trace(&[0;5],plain,false).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+/*
+T-table AES
+SubBytes, ShiftRows and MixColumns of a round as four lookups per column
+in 1 KiB tables generated at compile time, the lookups are indexed by
+secret bytes so this backend is only for hosts where cache timing is
+not a concern
+*/
+use crate::gf::{mul, INV_SBOX, SBOX};
+// Column word with row 0 in the low byte
+const fn table(sbox: &[u8;256], coefficients: [u8;4], rotate: u32) -> [u32;256] {
+  let mut t = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    let s = sbox[x];
+    let word = u32::from_le_bytes([mul(coefficients[0],s), mul(coefficients[1],s), mul(coefficients[2],s), mul(coefficients[3],s)]);
+    t[x] = word.rotate_left(rotate);
+    x += 1;
+  }
+  t
+}
+//
+const TE0: [u32;256] = table(&SBOX,[2,1,1,3],0);
+const TE1: [u32;256] = table(&SBOX,[2,1,1,3],8);
+const TE2: [u32;256] = table(&SBOX,[2,1,1,3],16);
+const TE3: [u32;256] = table(&SBOX,[2,1,1,3],24);
+const TD0: [u32;256] = table(&INV_SBOX,[14,9,13,11],0);
+const TD1: [u32;256] = table(&INV_SBOX,[14,9,13,11],8);
+const TD2: [u32;256] = table(&INV_SBOX,[14,9,13,11],16);
+const TD3: [u32;256] = table(&INV_SBOX,[14,9,13,11],24);
+//
+fn words(key: &[[u8;4];4]) -> [u32;4] {
+  [u32::from_le_bytes(key[0]), u32::from_le_bytes(key[1]), u32::from_le_bytes(key[2]), u32::from_le_bytes(key[3])]
+}
+//
+fn byte(word: u32, row: usize) -> usize {
+  ((word >> (8*row)) & 0xff) as usize
+}
+//
+fn load(block: &[u8;16], k: &[u32;4]) -> [u32;4] {
+  core::array::from_fn(|c| u32::from_le_bytes([block[4*c], block[4*c+1], block[4*c+2], block[4*c+3]]) ^ k[c])
+}
+//
+pub fn encrypt_block(block: [u8;16], keys: &[[[u8; 4] ;4]; 15], nr: usize) -> [u8;16] {
+  let mut blocks = [block];
+  encrypt_blocks(&mut blocks,keys,nr);
+  blocks[0]
+}
+//
+pub fn decrypt_block(block: [u8;16], keys: &[[[u8; 4] ;4]; 15], nr: usize) -> [u8;16] {
+  let mut blocks = [block];
+  decrypt_blocks(&mut blocks,keys,nr);
+  blocks[0]
+}
+// Up to four blocks in place, a round is done on all of them before the
+// next so the independent lookups overlap
+pub fn encrypt_blocks(blocks: &mut [[u8;16]], keys: &[[[u8; 4] ;4]; 15], nr: usize) {
+  assert!(blocks.len() <= 4,"at most four blocks");
+  let n = blocks.len();
+  let k = words(&keys[0]);
+  let mut states:[[u32;4];4] = [[0;4];4];
+  for (s, block) in states.iter_mut().zip(blocks.iter()) {
+    *s = load(block,&k);
+  }
+  for key in keys.iter().take(nr).skip(1) {
+    let k = words(key);
+    for s in states[..n].iter_mut() {
+      let mut t:[u32;4] = [0;4];
+      for c in 0..4 {
+        t[c] = TE0[byte(s[c],0)] ^ TE1[byte(s[(c+1)%4],1)] ^ TE2[byte(s[(c+2)%4],2)] ^ TE3[byte(s[(c+3)%4],3)] ^ k[c];
+      }
+      *s = t;
+    }
+  }
+  let k = words(&keys[nr]);
+  for (s, block) in states.iter().zip(blocks.iter_mut()) {
+    for c in 0..4 {
+      let column = [SBOX[byte(s[c],0)], SBOX[byte(s[(c+1)%4],1)], SBOX[byte(s[(c+2)%4],2)], SBOX[byte(s[(c+3)%4],3)]];
+      block[4*c..4*c+4].copy_from_slice(&(u32::from_le_bytes(column) ^ k[c]).to_le_bytes());
+    }
+  }
+}
+// Equivalent inverse cipher with the dec schedule
+pub fn decrypt_blocks(blocks: &mut [[u8;16]], keys: &[[[u8; 4] ;4]; 15], nr: usize) {
+  assert!(blocks.len() <= 4,"at most four blocks");
+  let n = blocks.len();
+  let k = words(&keys[nr]);
+  let mut states:[[u32;4];4] = [[0;4];4];
+  for (s, block) in states.iter_mut().zip(blocks.iter()) {
+    *s = load(block,&k);
+  }
+  for key in keys.iter().take(nr).skip(1).rev() {
+    let k = words(key);
+    for s in states[..n].iter_mut() {
+      let mut t:[u32;4] = [0;4];
+      for c in 0..4 {
+        t[c] = TD0[byte(s[c],0)] ^ TD1[byte(s[(c+3)%4],1)] ^ TD2[byte(s[(c+2)%4],2)] ^ TD3[byte(s[(c+1)%4],3)] ^ k[c];
+      }
+      *s = t;
+    }
+  }
+  let k = words(&keys[0]);
+  for (s, block) in states.iter().zip(blocks.iter_mut()) {
+    for c in 0..4 {
+      let column = [INV_SBOX[byte(s[c],0)], INV_SBOX[byte(s[(c+3)%4],1)], INV_SBOX[byte(s[(c+2)%4],2)], INV_SBOX[byte(s[(c+1)%4],3)]];
+      block[4*c..4*c+4].copy_from_slice(&(u32::from_le_bytes(column) ^ k[c]).to_le_bytes());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AesKey, Backend};
+    #[test]
+    fn test_ttable_matches_table() {
+       assert_eq!(TE0[0x00],0xa56363c6);
+       let mut block:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       for len in [16,24,32] {
+         let key:Vec<u8> = (0..len as u8).collect();
+         let table = AesKey::new(&key).unwrap();
+         let fast = AesKey::with_backend(&key,Backend::TTable).unwrap();
+         for _ in 0..8 {
+           let cipher = fast.encrypt_block(block);
+           assert_eq!(cipher,table.encrypt_block(block));
+           assert_eq!(fast.decrypt_block(cipher),block);
+           block = cipher;
+         }
+       }
+    }
+}
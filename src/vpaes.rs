@@ -0,0 +1,459 @@
+/*
+SSSE3 vector permute backend, in the style of Hamburg's vpaes
+The S-box goes through the tower field GF((2^4)^2): a change of basis,
+the inversion with GF(2^4) arithmetic and the way back (with the affine
+transform) are all 16-entry tables looked up with pshufb on nibbles, so
+there is no secret dependent memory access. Every constant is derived at
+compile time from the AES polynomial
+*/
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+use crate::gf::{affine, inv_affine};
+//
+type RoundKeys = [[[u8; 4] ;4]; 15];
+// GF(2^4) modulo z^4 + z + 1
+const fn mul16(a: u8, b: u8) -> u8 {
+  let mut a = a;
+  let mut b = b;
+  let mut result = 0;
+  while b != 0 {
+    if b & 1 != 0 {
+      result ^= a;
+    }
+    a <<= 1;
+    if a & 0x10 != 0 {
+      a ^= 0x13;
+    }
+    b >>= 1;
+  }
+  result
+}
+// y^2 + y + LAMBDA is irreducible over GF(2^4)
+const fn find_lambda() -> u8 {
+  let mut lambda = 1;
+  loop {
+    let mut root = false;
+    let mut n = 0;
+    while n < 16 {
+      if mul16(n,n) ^ n ^ lambda == 0 {
+        root = true;
+      }
+      n += 1;
+    }
+    if !root {
+      return lambda;
+    }
+    lambda += 1;
+  }
+}
+const LAMBDA: u8 = find_lambda();
+// a = a1 y + a0 with a1 in the high nibble
+const fn tower_mul(a: u8, b: u8) -> u8 {
+  let (a1, a0, b1, b0) = (a >> 4, a & 15, b >> 4, b & 15);
+  let high = mul16(a1,b1);
+  ((high ^ mul16(a1,b0) ^ mul16(a0,b1)) << 4) | (mul16(high,LAMBDA) ^ mul16(a0,b0))
+}
+// beta^8 + beta^4 + beta^3 + beta + 1 = 0, so x -> beta is a field isomorphism
+const fn find_beta() -> u8 {
+  let mut beta = 2;
+  loop {
+    let mut powers = [1u8; 9];
+    let mut i = 1;
+    while i < 9 {
+      powers[i] = tower_mul(powers[i-1],beta);
+      i += 1;
+    }
+    if powers[8] ^ powers[4] ^ powers[3] ^ powers[1] ^ powers[0] == 0 {
+      return beta;
+    }
+    beta += 1;
+  }
+}
+// AES byte to tower element
+const fn to_tower_table() -> [u8;256] {
+  let beta = find_beta();
+  let mut basis = [1u8; 8];
+  let mut i = 1;
+  while i < 8 {
+    basis[i] = tower_mul(basis[i-1],beta);
+    i += 1;
+  }
+  let mut table = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    let mut i = 0;
+    while i < 8 {
+      if x & (1 << i) != 0 {
+        table[x] ^= basis[i];
+      }
+      i += 1;
+    }
+    x += 1;
+  }
+  table
+}
+const TO_TOWER: [u8;256] = to_tower_table();
+//
+const fn from_tower_table() -> [u8;256] {
+  let mut table = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    table[TO_TOWER[x] as usize] = x as u8;
+    x += 1;
+  }
+  table
+}
+const FROM_TOWER: [u8;256] = from_tower_table();
+// low and high nibble tables of the linear map f
+const fn nibbles(f: &[u8;256], high: bool) -> [u8;16] {
+  let mut table = [0;16];
+  let mut n = 0;
+  while n < 16 {
+    table[n] = f[if high { n << 4 } else { n }];
+    n += 1;
+  }
+  table
+}
+// linear part of the affine transform after leaving the tower
+const fn sbox_out_table() -> [u8;256] {
+  let mut table = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    table[x] = affine(FROM_TOWER[x],0);
+    x += 1;
+  }
+  table
+}
+// linear part of the inverse affine transform before entering the tower
+const fn inv_sbox_in_table() -> [u8;256] {
+  let mut table = [0;256];
+  let mut x = 0;
+  while x < 256 {
+    table[x] = TO_TOWER[inv_affine(x as u8,0) as usize];
+    x += 1;
+  }
+  table
+}
+// S-box: into the tower, invert, back out through the affine transform ^ 0x63
+const SBOX_IN: [u8;256] = TO_TOWER;
+const SBOX_OUT: [u8;256] = sbox_out_table();
+// inverse S-box: inverse affine transform into the tower (^ TO_TOWER[0x05]), invert, back out
+const INV_SBOX_IN: [u8;256] = inv_sbox_in_table();
+//
+const fn square_table(lambda: u8) -> [u8;16] {
+  let mut table = [0;16];
+  let mut n = 0;
+  while n < 16 {
+    table[n] = mul16(mul16(n as u8,n as u8),lambda);
+    n += 1;
+  }
+  table
+}
+//
+const fn inverse16_table() -> [u8;16] {
+  let mut table = [0;16];
+  let mut a = 1;
+  while a < 16 {
+    let mut b = 1;
+    while b < 16 {
+      if mul16(a,b) == 1 {
+        table[a as usize] = b;
+      }
+      b += 1;
+    }
+    a += 1;
+  }
+  table
+}
+// multiplication by z^i
+const fn times_table(i: u32) -> [u8;16] {
+  let mut table = [0;16];
+  let mut n = 0;
+  while n < 16 {
+    table[n] = mul16(n as u8,1 << i);
+    n += 1;
+  }
+  table
+}
+//
+const TABLES: [[u8;16]; 14] = [
+  nibbles(&SBOX_IN,false), nibbles(&SBOX_IN,true),
+  nibbles(&SBOX_OUT,false), nibbles(&SBOX_OUT,true),
+  nibbles(&INV_SBOX_IN,false), nibbles(&INV_SBOX_IN,true),
+  nibbles(&FROM_TOWER,false), nibbles(&FROM_TOWER,true),
+  square_table(1), square_table(LAMBDA), inverse16_table(),
+  times_table(1), times_table(2), times_table(3),
+];
+// ShiftRows and the column rotations as byte permutations
+const SHIFT_ROWS: [u8;16] = [0, 5, 10, 15, 4, 9, 14, 3, 8, 13, 2, 7, 12, 1, 6, 11];
+const INV_SHIFT_ROWS: [u8;16] = [0, 13, 10, 7, 4, 1, 14, 11, 8, 5, 2, 15, 12, 9, 6, 3];
+const ROTATE_1: [u8;16] = [1, 2, 3, 0, 5, 6, 7, 4, 9, 10, 11, 8, 13, 14, 15, 12];
+const ROTATE_2: [u8;16] = [2, 3, 0, 1, 6, 7, 4, 5, 10, 11, 8, 9, 14, 15, 12, 13];
+//
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), feature = "std"))]
+pub fn available() -> bool {
+  is_x86_feature_detected!("ssse3")
+}
+// No std, only -C target-feature=+ssse3 enables it
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), not(feature = "std")))]
+pub fn available() -> bool {
+  cfg!(target_feature = "ssse3")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn available() -> bool {
+  false
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn load(bytes: &[u8;16]) -> __m128i {
+  // SAFETY: 16 readable bytes, loadu has no alignment requirement
+  unsafe { _mm_loadu_si128(bytes.as_ptr() as *const __m128i) }
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn store(value: __m128i) -> [u8;16] {
+  let mut bytes:[u8;16] = [0;16];
+  // SAFETY: 16 writable bytes, storeu has no alignment requirement
+  unsafe { _mm_storeu_si128(bytes.as_mut_ptr() as *mut __m128i, value) };
+  bytes
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+struct Vectors {
+  t: [__m128i; 14],
+  low: __m128i,
+  shift_rows: __m128i,
+  inv_shift_rows: __m128i,
+  rotate_1: __m128i,
+  rotate_2: __m128i,
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn vectors() -> Vectors {
+  Vectors {
+    t: TABLES.map(|table| load(&table)),
+    low: _mm_set1_epi8(0x0f),
+    shift_rows: load(&SHIFT_ROWS),
+    inv_shift_rows: load(&INV_SHIFT_ROWS),
+    rotate_1: load(&ROTATE_1),
+    rotate_2: load(&ROTATE_2),
+  }
+}
+// table[low nibble] ^ table[high nibble] for a linear map
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn linear(v: &Vectors, x: __m128i, low: usize) -> __m128i {
+  let lo = _mm_and_si128(x,v.low);
+  let hi = _mm_and_si128(_mm_srli_epi16::<4>(x),v.low);
+  _mm_xor_si128(_mm_shuffle_epi8(v.t[low],lo),_mm_shuffle_epi8(v.t[low+1],hi))
+}
+// a * b in GF(2^4), bit i of b selects a * z^i
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn mul_nibbles(v: &Vectors, a: __m128i, b: __m128i) -> __m128i {
+  let mut result = _mm_and_si128(a,_mm_cmpeq_epi8(_mm_and_si128(b,_mm_set1_epi8(1)),_mm_set1_epi8(1)));
+  for (i, bit) in [2, 4, 8].iter().enumerate() {
+    let mask = _mm_cmpeq_epi8(_mm_and_si128(b,_mm_set1_epi8(*bit)),_mm_set1_epi8(*bit));
+    result = _mm_xor_si128(result,_mm_and_si128(_mm_shuffle_epi8(v.t[11+i],a),mask));
+  }
+  result
+}
+// (a1 y + a0)^-1 = (a1 y + a0 + a1) / (LAMBDA a1^2 + a1 a0 + a0^2)
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn tower_inverse(v: &Vectors, x: __m128i) -> __m128i {
+  let a0 = _mm_and_si128(x,v.low);
+  let a1 = _mm_and_si128(_mm_srli_epi16::<4>(x),v.low);
+  let delta = _mm_xor_si128(_mm_xor_si128(_mm_shuffle_epi8(v.t[9],a1),_mm_shuffle_epi8(v.t[8],a0)),mul_nibbles(v,a1,a0));
+  let d = _mm_shuffle_epi8(v.t[10],delta);
+  let b1 = mul_nibbles(v,a1,d);
+  let b0 = mul_nibbles(v,_mm_xor_si128(a0,a1),d);
+  _mm_or_si128(_mm_slli_epi16::<4>(b1),b0)
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn sub_bytes(v: &Vectors, x: __m128i) -> __m128i {
+  let inverse = tower_inverse(v,linear(v,x,0));
+  _mm_xor_si128(linear(v,inverse,2),_mm_set1_epi8(0x63))
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn inv_sub_bytes(v: &Vectors, x: __m128i) -> __m128i {
+  let tower = _mm_xor_si128(linear(v,x,4),_mm_set1_epi8(TO_TOWER[0x05] as i8));
+  linear(v,tower_inverse(v,tower),6)
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn xtime(x: __m128i) -> __m128i {
+  let carry = _mm_and_si128(_mm_cmplt_epi8(x,_mm_setzero_si128()),_mm_set1_epi8(0x1b));
+  _mm_xor_si128(_mm_add_epi8(x,x),carry)
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn mix_columns(v: &Vectors, x: __m128i) -> __m128i {
+  let t = _mm_xor_si128(x,_mm_shuffle_epi8(x,v.rotate_1));
+  let all = _mm_xor_si128(t,_mm_shuffle_epi8(t,v.rotate_2));
+  _mm_xor_si128(_mm_xor_si128(x,xtime(t)),all)
+}
+// (4x^2 + 5) then MixColumns
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn inv_mix_columns(v: &Vectors, x: __m128i) -> __m128i {
+  let u = xtime(xtime(_mm_xor_si128(x,_mm_shuffle_epi8(x,v.rotate_2))));
+  mix_columns(v,_mm_xor_si128(x,u))
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn round_key(key: &[[u8;4];4]) -> __m128i {
+  load(key.as_flattened().try_into().unwrap())
+}
+// The tables and round keys are loaded once for all the blocks, four
+// blocks go through each round together
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn encrypt_blocks_ssse3(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  let v = vectors();
+  let k = keys.map(|key| round_key(&key));
+  for chunk in blocks.chunks_mut(4) {
+    let mut state = [_mm_setzero_si128(); 4];
+    for (s, block) in state.iter_mut().zip(chunk.iter()) {
+      *s = _mm_xor_si128(load(block),k[0]);
+    }
+    for (i, key) in k.iter().enumerate().take(nr+1).skip(1) {
+      for s in state[..chunk.len()].iter_mut() {
+        *s = _mm_shuffle_epi8(sub_bytes(&v,*s),v.shift_rows);
+        if i != nr {
+          *s = mix_columns(&v,*s);
+        }
+        *s = _mm_xor_si128(*s,*key);
+      }
+    }
+    for (s, block) in state.iter().zip(chunk.iter_mut()) {
+      *block = store(*s);
+    }
+  }
+}
+// Equivalent inverse cipher with the dec schedule
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "ssse3")]
+fn decrypt_blocks_ssse3(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  let v = vectors();
+  let k = keys.map(|key| round_key(&key));
+  for chunk in blocks.chunks_mut(4) {
+    let mut state = [_mm_setzero_si128(); 4];
+    for (s, block) in state.iter_mut().zip(chunk.iter()) {
+      *s = _mm_xor_si128(load(block),k[nr]);
+    }
+    for (i, key) in k.iter().enumerate().take(nr).rev() {
+      for s in state[..chunk.len()].iter_mut() {
+        *s = _mm_shuffle_epi8(inv_sub_bytes(&v,*s),v.inv_shift_rows);
+        if i != 0 {
+          *s = inv_mix_columns(&v,*s);
+        }
+        *s = _mm_xor_si128(*s,*key);
+      }
+    }
+    for (s, block) in state.iter().zip(chunk.iter_mut()) {
+      *block = store(*s);
+    }
+  }
+}
+// The safe functions panic when the CPU has no SSSE3, check available first
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn encrypt_block(block: [u8;16], keys: &RoundKeys, nr: usize) -> [u8;16] {
+  let mut blocks = [block];
+  encrypt_blocks(&mut blocks,keys,nr);
+  blocks[0]
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn decrypt_block(block: [u8;16], keys: &RoundKeys, nr: usize) -> [u8;16] {
+  let mut blocks = [block];
+  decrypt_blocks(&mut blocks,keys,nr);
+  blocks[0]
+}
+// Any number of blocks in place
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn encrypt_blocks(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  assert!(available(),"SSSE3 is not available");
+  // SAFETY: the CPU feature was detected above
+  unsafe { encrypt_blocks_ssse3(blocks,keys,nr) }
+}
+//
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn decrypt_blocks(blocks: &mut [[u8;16]], keys: &RoundKeys, nr: usize) {
+  assert!(available(),"SSSE3 is not available");
+  // SAFETY: the CPU feature was detected above
+  unsafe { decrypt_blocks_ssse3(blocks,keys,nr) }
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn encrypt_block(_block: [u8;16], _keys: &RoundKeys, _nr: usize) -> [u8;16] {
+  panic!("SSSE3 is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn decrypt_block(_block: [u8;16], _keys: &RoundKeys, _nr: usize) -> [u8;16] {
+  panic!("SSSE3 is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn encrypt_blocks(_blocks: &mut [[u8;16]], _keys: &RoundKeys, _nr: usize) {
+  panic!("SSSE3 is not available")
+}
+//
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+pub fn decrypt_blocks(_blocks: &mut [[u8;16]], _keys: &RoundKeys, _nr: usize) {
+  panic!("SSSE3 is not available")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gf::{INV_SBOX, SBOX};
+    use crate::{AesKey, Backend};
+    #[test]
+    fn test_tower_tables() {
+       for x in 0..=255u8 {
+         let inverse = crate::gf::inverse(x);
+         assert_eq!(FROM_TOWER[TO_TOWER[x as usize] as usize],x);
+         assert_eq!(SBOX_OUT[TO_TOWER[inverse as usize] as usize]^0x63,SBOX[x as usize]);
+       }
+    }
+    #[test]
+    fn test_vpaes_matches_table() {
+       if !available() {
+         return;
+       }
+       let v = unsafe { vectors() };
+       for start in (0..256).step_by(16) {
+         let bytes:[u8;16] = core::array::from_fn(|j| (start+j) as u8);
+         assert_eq!(store(unsafe { sub_bytes(&v,load(&bytes)) }),SBOX[start..start+16]);
+         assert_eq!(store(unsafe { inv_sub_bytes(&v,load(&bytes)) }),INV_SBOX[start..start+16]);
+       }
+       let plain:[u8;16]=[0x00,0x11,0x22,0x33,0x44,0x55,0x66,0x77,0x88,0x99,0xaa,0xbb,0xcc,0xdd,0xee,0xff];
+       for len in [16,24,32] {
+         let key:Vec<u8> = (0..len as u8).collect();
+         let table = AesKey::new(&key).unwrap();
+         let vector = AesKey::with_backend(&key,Backend::Vpaes).unwrap();
+         assert_eq!(vector.backend,Backend::Vpaes);
+         let cipher = vector.encrypt_block(plain);
+         assert_eq!(cipher,table.encrypt_block(plain));
+         assert_eq!(vector.decrypt_block(cipher),plain);
+         let mut blocks:Vec<[u8;16]> = (0..7).map(|i| [i as u8; 16]).collect();
+         let expected:Vec<[u8;16]> = blocks.iter().map(|block| table.encrypt_block(*block)).collect();
+         vector.encrypt_blocks(&mut blocks);
+         assert_eq!(blocks,expected);
+         vector.decrypt_blocks(&mut blocks);
+         assert_eq!(blocks,(0..7).map(|i| [i as u8; 16]).collect::<Vec<[u8;16]>>());
+       }
+    }
+}
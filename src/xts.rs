@@ -0,0 +1,171 @@
+/*
+XTS-AES
+IEEE 1619 / NIST SP 800-38E, the tweak is the encrypted data unit number
+multiplied by x for every block, a partial last block uses ciphertext
+stealing, the whole blocks go through the bulk path of the data key
+*/
+use crate::AesKey;
+#[cfg(feature = "std")]
+use crate::parallel::for_each_chunk;
+// Multiplication by x in GF(2^128), little endian, x^128 = x^7 + x^2 + x + 1
+fn mul_x(t:[u8;16]) -> [u8;16]{
+  let v = u128::from_le_bytes(t);
+  ((v << 1) ^ (0x87 * (v >> 127))).to_le_bytes()
+}
+//
+fn xor(a:&mut [u8;16], b:&[u8;16]){
+  for (x, y) in a.iter_mut().zip(b.iter()) {
+    *x ^= y;
+  }
+}
+// C = CIPH(P ^ T) ^ T for all the blocks at once
+fn xex(blocks:&mut [[u8;16]], tweaks:&[[u8;16]], encrypt:impl Fn(&mut [[u8;16]])){
+  for (block, t) in blocks.iter_mut().zip(tweaks.iter()) {
+    xor(block,t);
+  }
+  encrypt(blocks);
+  for (block, t) in blocks.iter_mut().zip(tweaks.iter()) {
+    xor(block,t);
+  }
+}
+// One data unit of at least 16 bytes, the tweak is usually the little
+// endian data unit (sector) number
+#[cfg(feature = "std")]
+pub fn xts_encrypt(data_key:&AesKey, tweak_key:&AesKey, tweak:[u8;16], input:&[u8]) -> Result<Vec<u8>, &'static str>{
+  let mut result = input.to_vec();
+  xts_encrypt_in_place(data_key,tweak_key,tweak,&mut result)?;
+  Ok(result)
+}
+//
+#[cfg(feature = "std")]
+pub fn xts_decrypt(data_key:&AesKey, tweak_key:&AesKey, tweak:[u8;16], input:&[u8]) -> Result<Vec<u8>, &'static str>{
+  let mut result = input.to_vec();
+  xts_decrypt_in_place(data_key,tweak_key,tweak,&mut result)?;
+  Ok(result)
+}
+// Never allocates, eight tweaks at a time on the stack
+pub fn xts_encrypt_in_place(data_key:&AesKey, tweak_key:&AesKey, tweak:[u8;16], data:&mut [u8]) -> Result<(), &'static str>{
+  xts(data_key,tweak_key,tweak,data,true)
+}
+//
+pub fn xts_decrypt_in_place(data_key:&AesKey, tweak_key:&AesKey, tweak:[u8;16], data:&mut [u8]) -> Result<(), &'static str>{
+  xts(data_key,tweak_key,tweak,data,false)
+}
+// IEEE 1619 and SP 800-38E require two different keys
+fn check_keys(data_key:&AesKey, tweak_key:&AesKey) -> Result<(), &'static str>{
+  if data_key.rounds == tweak_key.rounds && data_key.enc == tweak_key.enc {
+    return Err("data key and tweak key must differ");
+  }
+  Ok(())
+}
+//
+fn xts(data_key:&AesKey, tweak_key:&AesKey, tweak:[u8;16], data:&mut [u8], encrypt:bool) -> Result<(), &'static str>{
+  check_keys(data_key,tweak_key)?;
+  if data.len() < 16 {
+    return Err("data unit is shorter than a block");
+  }
+  let m = data.len()/16;
+  let r = data.len()%16;
+  let cipher = |blocks:&mut [[u8;16]]| if encrypt { data_key.encrypt_blocks(blocks) } else { data_key.decrypt_blocks(blocks) };
+  // the last whole block goes with the stolen tail
+  let whole = if r == 0 { m } else { m-1 };
+  let mut t = tweak_key.encrypt_block(tweak);
+  for chunk in data[..16*whole].chunks_mut(8*16) {
+    let blocks = chunk.as_chunks_mut().0;
+    let mut tweaks:[[u8;16];8] = [[0;16];8];
+    for tweak in tweaks[..blocks.len()].iter_mut() {
+      *tweak = t;
+      t = mul_x(t);
+    }
+    xex(blocks,&tweaks,cipher);
+  }
+  if r == 0 {
+    return Ok(());
+  }
+  // T(m-1) and T(m), swapped when decrypting
+  let (first, second) = if encrypt { (t, mul_x(t)) } else { (mul_x(t), t) };
+  let (last, tail) = data[16*(m-1)..].split_at_mut(16);
+  let last:&mut [u8;16] = last.try_into().unwrap();
+  xex(core::slice::from_mut(last),&[first],cipher);
+  last[..r].swap_with_slice(tail);
+  xex(core::slice::from_mut(last),&[second],cipher);
+  Ok(())
+}
+// Consecutive data units of unit_len bytes numbered from first_unit, the
+// last one may be shorter but not below a block, on a pool of threads
+#[cfg(feature = "std")]
+pub fn xts_encrypt_units(data_key:&AesKey, tweak_key:&AesKey, first_unit:u128, unit_len:usize, input:&[u8], threads:usize) -> Result<Vec<u8>, &'static str>{
+  xts_units(data_key,tweak_key,first_unit,unit_len,input,threads,true)
+}
+//
+#[cfg(feature = "std")]
+pub fn xts_decrypt_units(data_key:&AesKey, tweak_key:&AesKey, first_unit:u128, unit_len:usize, input:&[u8], threads:usize) -> Result<Vec<u8>, &'static str>{
+  xts_units(data_key,tweak_key,first_unit,unit_len,input,threads,false)
+}
+//
+#[cfg(feature = "std")]
+fn xts_units(data_key:&AesKey, tweak_key:&AesKey, first_unit:u128, unit_len:usize, input:&[u8], threads:usize, encrypt:bool) -> Result<Vec<u8>, &'static str>{
+  check_keys(data_key,tweak_key)?;
+  if unit_len < 16 || (1..16).contains(&(input.len()%unit_len)) {
+    return Err("data unit is shorter than a block");
+  }
+  let mut result = input.to_vec();
+  for_each_chunk(threads,&mut result,unit_len,|index, unit| {
+    let tweak = first_unit.wrapping_add(index as u128).to_le_bytes();
+    xts(data_key,tweak_key,tweak,unit,encrypt).expect("keys and data unit length were checked");
+  });
+  Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::unhex;
+    use crate::Backend;
+    #[test]
+    fn test_xts() {
+       // AES-256-XTS, data unit 12345
+       let k1:Vec<u8> = (0..32).map(|i| ((i*3)%256) as u8).collect();
+       let k2:Vec<u8> = (0..32).map(|i| ((i*5+1)%256) as u8).collect();
+       let tweak = 12345u128.to_le_bytes();
+       let expected = unhex("51c9a838d34dab1e8c905079c6685d84ba506b4605e774151ca55beb0d8dc990dfd0fc8038e858cacb95ab79e6395ac431c37473e1baba25138fb5c6ae32869ff87d2f8f210e6b16f9374849b7591187f5f671106d88fad6106093d2f59100b4d905bc92");
+       for backend in [Backend::Table, Backend::AesNi, Backend::TTable, Backend::Bitsliced] {
+         let data_key = AesKey::with_backend(&k1,backend).unwrap();
+         let tweak_key = AesKey::with_backend(&k2,backend).unwrap();
+         for len in [64, 100] {
+           let plain:Vec<u8> = (0..len).map(|i| ((i*7+1)%256) as u8).collect();
+           let cipher = xts_encrypt(&data_key,&tweak_key,tweak,&plain).unwrap();
+           assert_eq!(cipher,expected[..len]);
+           assert_eq!(xts_decrypt(&data_key,&tweak_key,tweak,&cipher).unwrap(),plain);
+         }
+         let plain:Vec<u8> = (0..17).map(|i| ((i*7+1)%256) as u8).collect();
+         let cipher = xts_encrypt(&data_key,&tweak_key,tweak,&plain).unwrap();
+         assert_eq!(cipher,unhex("7bfe86c895681fc3a3178c8445bc377451"));
+         assert_eq!(xts_decrypt(&data_key,&tweak_key,tweak,&cipher).unwrap(),plain);
+         let mut data = cipher.clone();
+         xts_decrypt_in_place(&data_key,&tweak_key,tweak,&mut data).unwrap();
+         xts_encrypt_in_place(&data_key,&tweak_key,tweak,&mut data).unwrap();
+         assert_eq!(data,cipher);
+         assert!(xts_encrypt(&data_key,&tweak_key,tweak,&plain[..15]).is_err());
+       }
+    }
+    #[test]
+    fn test_xts_units() {
+       let data_key = AesKey::with_backend(&[1; 16],Backend::detect()).unwrap();
+       let tweak_key = AesKey::with_backend(&[2; 16],Backend::detect()).unwrap();
+       let input:Vec<u8> = (0..5*512+100).map(|i| (i*5) as u8).collect();
+       let mut expected:Vec<u8> = vec![];
+       for (i, unit) in input.chunks(512).enumerate() {
+         expected.extend(xts_encrypt(&data_key,&tweak_key,(7+i as u128).to_le_bytes(),unit).unwrap());
+       }
+       for threads in [1, 4] {
+         let cipher = xts_encrypt_units(&data_key,&tweak_key,7,512,&input,threads).unwrap();
+         assert_eq!(cipher,expected);
+         assert_eq!(xts_decrypt_units(&data_key,&tweak_key,7,512,&cipher,threads).unwrap(),input);
+       }
+       assert!(xts_encrypt_units(&data_key,&tweak_key,0,512,&input[..520],2).is_err());
+       let same_key = AesKey::with_backend(&[1; 16],Backend::Table).unwrap();
+       assert_eq!(xts_encrypt_units(&data_key,&same_key,0,512,&input,2),Err("data key and tweak key must differ"));
+       assert!(xts_encrypt(&data_key,&same_key,[0; 16],&input[..32]).is_err());
+    }
+}